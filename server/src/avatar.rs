@@ -0,0 +1,37 @@
+use crate::colour::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Avatar {
+    pub head_colour: Color,
+    pub torso_colour: Color,
+    pub left_arm_colour: Color,
+    pub right_arm_colour: Color,
+    pub left_leg_colour: Color,
+    pub right_leg_colour: Color,
+
+    // Asset ids, 0 means nothing is worn
+    pub face: u32,
+    pub hats: [u32; 3],
+    pub shirt: u32,
+    pub pants: u32,
+    pub tshirt: u32,
+    pub tool: u32,
+}
+
+// The plain avatar every player starts with, until Game::set_avatar gives them another
+pub fn new() -> Avatar {
+    return Avatar {
+        head_colour: Color::from(0xF3B700),
+        torso_colour: Color::from(0x85AD00),
+        left_arm_colour: Color::from(0xF3B700),
+        right_arm_colour: Color::from(0xF3B700),
+        left_leg_colour: Color::from(0x650013),
+        right_leg_colour: Color::from(0x650013),
+        face: 0,
+        hats: [0; 3],
+        shirt: 0,
+        pants: 0,
+        tshirt: 0,
+        tool: 0,
+    };
+}
@@ -0,0 +1,167 @@
+use crate::{
+    avatar::{self, Avatar},
+    brick::{Brick, Vector3},
+};
+
+// A bot counts as touching a player whose position is inside this box around it
+const TOUCH_RADIUS: f32 = 2.0;
+const TOUCH_HEIGHT: f32 = 5.0;
+
+// Close enough to a target to count as there
+const ARRIVE_DISTANCE: f32 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Behaviour {
+    Idle,
+    // Walks to a point, then goes idle
+    MoveTo(Vector3),
+    // Turns to face whichever player is closest
+    LookAtNearestPlayer,
+    // Walks from brick to brick, starting over once the last one is reached
+    FollowPath { points: Vec<Vector3>, next: usize },
+}
+
+pub struct Bot {
+    pub net_id: u32,
+    pub name: String,
+    pub avatar: Avatar,
+
+    pub position: Vector3,
+    // Degrees around the vertical axis
+    pub rotation: f32,
+    // Studs per second
+    pub speed: f32,
+
+    pub behaviour: Behaviour,
+
+    // Players the bot was touching last tick, so touches only fire once
+    pub touching: Vec<u32>,
+    // Set when the position changed since the last tick sent it out
+    pub moved: bool,
+}
+
+pub fn new(name: String) -> Bot {
+    return Bot {
+        net_id: 0,
+        name,
+        avatar: avatar::new(),
+        position: Vector3::default(),
+        rotation: 0.0,
+        speed: 4.0,
+        behaviour: Behaviour::Idle,
+        touching: vec![],
+        moved: false,
+    };
+}
+
+impl Bot {
+    pub fn set_position(&mut self, position: Vector3, rotation: f32) {
+        self.position = position;
+        self.rotation = rotation;
+        self.moved = true;
+    }
+
+    pub fn move_to(&mut self, target: Vector3) {
+        self.behaviour = Behaviour::MoveTo(target);
+    }
+
+    pub fn look_at_nearest_player(&mut self) {
+        self.behaviour = Behaviour::LookAtNearestPlayer;
+    }
+
+    // Walks across the top of each brick in turn
+    pub fn follow_path(&mut self, bricks: &[Brick]) {
+        if bricks.is_empty() {
+            self.behaviour = Behaviour::Idle;
+            return;
+        }
+
+        let points = bricks.iter().map(|brick| brick.top_centre()).collect();
+        self.behaviour = Behaviour::FollowPath { points, next: 0 };
+    }
+
+    pub fn stop(&mut self) {
+        self.behaviour = Behaviour::Idle;
+    }
+
+    pub fn touches(&self, position: Vector3) -> bool {
+        return (position.x - self.position.x).abs() <= TOUCH_RADIUS
+            && (position.y - self.position.y).abs() <= TOUCH_RADIUS
+            && position.z >= self.position.z - TOUCH_HEIGHT
+            && position.z <= self.position.z + TOUCH_HEIGHT;
+    }
+
+    fn face(&mut self, target: Vector3) {
+        let x = target.x - self.position.x;
+        let y = target.y - self.position.y;
+        if x == 0.0 && y == 0.0 {
+            return;
+        }
+
+        let rotation = y.atan2(x).to_degrees();
+        if rotation != self.rotation {
+            self.rotation = rotation;
+            self.moved = true;
+        }
+    }
+
+    // Moves up to `seconds` worth of speed towards `target`, returns whether it got there
+    fn step_towards(&mut self, target: Vector3, seconds: f32) -> bool {
+        let x = target.x - self.position.x;
+        let y = target.y - self.position.y;
+        let z = target.z - self.position.z;
+        let distance = (x * x + y * y + z * z).sqrt();
+
+        if distance <= ARRIVE_DISTANCE {
+            return true;
+        }
+
+        self.face(target);
+        self.moved = true;
+
+        let step = self.speed * seconds;
+        if step >= distance {
+            self.position = target;
+            return true;
+        }
+
+        self.position.x += x / distance * step;
+        self.position.y += y / distance * step;
+        self.position.z += z / distance * step;
+        return false;
+    }
+
+    // Runs the behaviour for one tick, `players` being where everyone alive is standing
+    pub fn update(&mut self, seconds: f32, players: &[Vector3]) {
+        let target = match &self.behaviour {
+            Behaviour::Idle => return,
+            Behaviour::MoveTo(target) => *target,
+            Behaviour::FollowPath { points, next } => points[*next],
+            Behaviour::LookAtNearestPlayer => {
+                let distance = |position: &Vector3| {
+                    let x = position.x - self.position.x;
+                    let y = position.y - self.position.y;
+                    let z = position.z - self.position.z;
+                    x * x + y * y + z * z
+                };
+
+                let nearest = players
+                    .iter()
+                    .min_by(|a, b| distance(a).total_cmp(&distance(b)));
+                if let Some(nearest) = nearest {
+                    self.face(*nearest);
+                }
+                return;
+            }
+        };
+
+        if !self.step_towards(target, seconds) {
+            return;
+        }
+
+        match &mut self.behaviour {
+            Behaviour::FollowPath { points, next } => *next = (*next + 1) % points.len(),
+            _ => self.behaviour = Behaviour::Idle,
+        }
+    }
+}
@@ -1,47 +1,125 @@
 use std::fs;
 
-#[derive(Default, Debug)]
+use crate::{
+    colour::Color,
+    team::{self, Team},
+    tool::{self, Tool},
+};
+
+pub const CLICK_DISTANCE: f32 = 50.0;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub struct Vector3 {
     pub x: f32,
     pub y: f32,
     pub z: f32,
 }
 
+impl Vector3 {
+    pub fn is_finite(&self) -> bool {
+        return self.x.is_finite() && self.y.is_finite() && self.z.is_finite();
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Brick {
     pub net_id: u32,
 
     pub name: String,
-    pub colour: String,
+    pub colour: Color,
     pub shape: String,
 
     pub position: Vector3,
     pub scale: Vector3,
     pub light_enabled: bool,
-    pub light_colour: String,
+    pub light_colour: Color,
     pub light_range: u32,
     pub visibility: f32,
     pub rotation: i32,
 
     pub collision: bool,
+
+    pub clickable: bool,
+    // How far away a player can be and still click the brick
+    pub click_distance: f32,
+}
+
+impl Brick {
+    // Where something standing on the brick ends up
+    pub fn top_centre(&self) -> Vector3 {
+        return Vector3 {
+            x: self.position.x + self.scale.x / 2.0,
+            y: self.position.y + self.scale.y / 2.0,
+            z: self.position.z + self.scale.z,
+        };
+    }
 }
 
 #[derive(Default)]
 pub struct Environment {
-    ambient: String,
-    sky_colour: String,
-    base_colour: String,
+    pub ambient: Color,
+    pub sky_colour: Color,
+    pub base_colour: Color,
 }
 
-fn hex(r: f32, g: f32, b: f32) -> String {
-    format!(
-        "{:02X}{:02X}{:02X}",
-        r as f32 as u8, g as f32 as u8, b as f32 as u8
-    )
+#[derive(Default)]
+pub struct Map {
+    pub environment: Environment,
+    pub bricks: Vec<Brick>,
+    pub teams: Vec<Team>,
+    // Tools every player starts with
+    pub tools: Vec<Tool>,
+}
+
+// What the `+ATTRIBUTE` lines currently apply to
+#[derive(PartialEq)]
+enum Section {
+    Brick,
+    Team,
+    Slot,
+    Other,
+}
+
+// Reads the "r g b" floats BRK files use for colours
+fn rgb(values: &[&str]) -> Option<Color> {
+    return Some(Color::from_rgb_f32(
+        values.first()?.parse::<f32>().ok()?,
+        values.get(1)?.parse::<f32>().ok()?,
+        values.get(2)?.parse::<f32>().ok()?,
+    ));
 }
 
-pub fn load_from_file(file_name: String) -> bool {
-    let contents = fs::read_to_string(file_name).expect("Something went wrong reading the file");
+// x y z, scale x y z, r g b, alpha
+fn parse_brick(data: &[&str]) -> Option<Brick> {
+    let float = |index: usize| data.get(index)?.parse::<f32>().ok();
+
+    return Some(Brick {
+        position: Vector3 {
+            x: float(0)?,
+            y: float(1)?,
+            z: float(2)?,
+        },
+        scale: Vector3 {
+            x: float(3)?,
+            y: float(4)?,
+            z: float(5)?,
+        },
+        colour: rgb(data.get(6..9)?)?,
+        visibility: float(9)?,
+        collision: true,
+        click_distance: CLICK_DISTANCE,
+        ..Brick::default()
+    });
+}
+
+pub fn load_from_file(file_name: String) -> Option<Map> {
+    let contents = match fs::read_to_string(&file_name) {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Couldn't read map {}: {}", file_name, e);
+            return None;
+        }
+    };
 
     let lines = contents.split("\n");
     let mut total_lines = 0;
@@ -49,10 +127,9 @@ pub fn load_from_file(file_name: String) -> bool {
     let mut environment = Environment::default();
 
     let mut bricks = Vec::<Brick>::new();
-    bricks.push(Brick {
-        collision: true,
-        ..Brick::default()
-    });
+    let mut teams = Vec::<Team>::new();
+    let mut tools = Vec::<Tool>::new();
+    let mut section = Section::Brick;
 
     for mut line in lines {
         total_lines += 1;
@@ -63,104 +140,212 @@ pub fn load_from_file(file_name: String) -> bool {
             1 => {
                 if line != "B R I C K  W O R K S H O P  V0.2.0.0" {
                     println!("Invalid file format");
-                    return false;
+                    return None;
                 }
                 continue;
             }
-            3 => {
+            3..=5 => {
                 let colours: Vec<&str> = line.split(" ").collect();
-                environment.ambient = hex(
-                    colours[0].parse::<f32>().unwrap(),
-                    colours[1].parse::<f32>().unwrap(),
-                    colours[2].parse::<f32>().unwrap(),
-                );
-                continue;
-            }
-            4 => {
-                let colours: Vec<&str> = line.split(" ").collect();
-                println!("{:?}", colours);
-                environment.base_colour = hex(
-                    colours[0].parse::<f32>().unwrap(),
-                    colours[1].parse::<f32>().unwrap(),
-                    colours[2].parse::<f32>().unwrap(),
-                );
-                continue;
-            }
-            5 => {
-                let colours: Vec<&str> = line.split(" ").collect();
-                environment.sky_colour = hex(
-                    colours[0].parse::<f32>().unwrap(),
-                    colours[1].parse::<f32>().unwrap(),
-                    colours[2].parse::<f32>().unwrap(),
-                );
+                let Some(colour) = rgb(&colours) else {
+                    println!("Invalid colour on line {} of the map", total_lines);
+                    return None;
+                };
+
+                match total_lines {
+                    3 => environment.ambient = colour,
+                    4 => environment.base_colour = colour,
+                    _ => environment.sky_colour = colour,
+                }
                 continue;
             }
             _ => {}
         }
 
-        let mut data: Vec<&str> = line.split(" ").collect();
-        let attribute = data[0].replace("+", "");
-        data.remove(0);
-        let value = data.join(" ");
+        if let Some(definition) = line.strip_prefix('>') {
+            let mut data: Vec<&str> = definition.split(" ").collect();
+            let kind = data.remove(0);
+            let name = data.join(" ");
 
-        let last_brick = bricks.last_mut().unwrap();
-
-        match attribute.as_str() {
-            "NAME" => {
-                last_brick.name = value;
-                continue;
+            match kind {
+                "TEAM" => {
+                    teams.push(team::new(name, Color::from(0xFFFFFF)));
+                    section = Section::Team;
+                }
+                "SLOT" => {
+                    tools.push(tool::new(name));
+                    section = Section::Slot;
+                }
+                _ => {
+                    section = Section::Other;
+                }
             }
-            "ROT" => {
-                last_brick.rotation = value.parse::<i32>().unwrap();
+            continue;
+        }
+
+        if let Some(attribute_line) = line.strip_prefix('+') {
+            let mut data: Vec<&str> = attribute_line.split(" ").collect();
+            let attribute = data.remove(0);
+            let value = data.join(" ");
+
+            if section == Section::Team {
+                let Some(last_team) = teams.last_mut() else {
+                    continue;
+                };
+
+                if attribute == "COLOR" {
+                    let colours: Vec<&str> = value.split(" ").collect();
+                    if let Some(colour) = rgb(&colours) {
+                        last_team.colour = colour;
+                    }
+                }
                 continue;
             }
-            "SHAPE" => {
-                last_brick.shape = value;
+            if section == Section::Slot {
+                let Some(last_tool) = tools.last_mut() else {
+                    continue;
+                };
+
+                if attribute == "MODEL" {
+                    last_tool.model = value.parse::<u32>().unwrap_or(0);
+                }
                 continue;
             }
-            "NOCOLLISION" => {
-                last_brick.collision = false;
+            if section != Section::Brick {
                 continue;
             }
-            "LIGHT" => {
-                let colours: Vec<&str> = value.split(" ").collect();
-
-                last_brick.light_enabled = true;
-                last_brick.light_range = colours[3].parse::<u32>().unwrap();
-                last_brick.light_colour = hex(
-                    colours[0].parse::<f32>().unwrap(),
-                    colours[1].parse::<f32>().unwrap(),
-                    colours[2].parse::<f32>().unwrap(),
-                );
+
+            // Attributes belong to the brick line above them
+            let Some(last_brick) = bricks.last_mut() else {
                 continue;
+            };
+
+            match attribute {
+                "NAME" => {
+                    last_brick.name = value;
+                }
+                "ROT" => {
+                    last_brick.rotation = value.parse::<i32>().unwrap_or(0);
+                }
+                "SHAPE" => {
+                    last_brick.shape = value;
+                }
+                "NOCOLLISION" => {
+                    last_brick.collision = false;
+                }
+                "CLICKABLE" => {
+                    last_brick.clickable = true;
+                    last_brick.click_distance = value.parse::<f32>().unwrap_or(CLICK_DISTANCE);
+                }
+                "LIGHT" => {
+                    // r g b range, a light missing any of them is left off
+                    let colours: Vec<&str> = value.split(" ").collect();
+                    let range = colours.get(3).and_then(|range| range.parse::<u32>().ok());
+
+                    if let (Some(colour), Some(range)) = (rgb(&colours), range) {
+                        last_brick.light_enabled = true;
+                        last_brick.light_range = range;
+                        last_brick.light_colour = colour;
+                    }
+                }
+                _ => {}
             }
-            _ => {}
+            continue;
         }
 
-        if data.len() == 9 {
-            last_brick.position = Vector3 {
-                x: data[0].parse::<f32>().unwrap(),
-                y: data[1].parse::<f32>().unwrap(),
-                z: data[2].parse::<f32>().unwrap(),
-            };
-            last_brick.scale = Vector3 {
-                x: data[3].parse::<f32>().unwrap(),
-                y: data[4].parse::<f32>().unwrap(),
-                z: data[5].parse::<f32>().unwrap(),
-            };
+        let data: Vec<&str> = line.split(" ").collect();
 
-            last_brick.colour = hex(
-                data[6].parse::<f32>().unwrap(),
-                data[7].parse::<f32>().unwrap(),
-                data[8].parse::<f32>().unwrap(),
-            );
+        if data.len() == 10 {
+            let Some(mut brick) = parse_brick(&data) else {
+                println!("Invalid brick on line {} of the map", total_lines);
+                return None;
+            };
 
-            bricks.push(Brick {
-                collision: true,
-                ..Brick::default()
-            });
+            section = Section::Brick;
+            brick.net_id = bricks.len() as u32;
+            bricks.push(brick);
         }
     }
 
-    return true;
+    return Some(Map {
+        environment,
+        bricks,
+        teams,
+        tools,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_map_is_none() {
+        assert!(load_from_file("does/not/exist.brk".to_string()).is_none());
+    }
+
+    const HEADER: &str =
+        "B R I C K  W O R K S H O P  V0.2.0.0\n\n0 0 0 1\n0.2 0.6 0.2 1\n0.5 0.7 1 1\n100\n400\n\n";
+
+    fn load(name: &str, contents: &str) -> Option<Map> {
+        let path = std::env::temp_dir().join(format!("{}-{}.brk", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+
+        let map = load_from_file(path.to_string_lossy().to_string());
+        fs::remove_file(&path).unwrap();
+        return map;
+    }
+
+    #[test]
+    fn loads_bricks_and_teams() {
+        let map = load(
+            "map",
+            &format!(
+                "{}-50 -50 -1 100 100 1 0.2 0.6 0.2 1\n\t+NAME Baseplate\n\
+                 0 0 0 4 4 1 1 0 0 1\n\t+NAME spawn\n\t+CLICKABLE 10\n\t+ROT 90\n\
+                 \t+LIGHT 1 0.5 0 20\n>TEAM Red\n\t+COLOR 1 0 0\n",
+                HEADER
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(map.bricks.len(), 2);
+        assert_eq!(map.bricks[0].name, "Baseplate");
+        assert_eq!(map.bricks[1].name, "spawn");
+        assert!(map.bricks[1].clickable);
+        assert_eq!(map.bricks[1].click_distance, 10.0);
+        assert_eq!(map.bricks[1].rotation, 90);
+        assert!(map.bricks[1].light_enabled);
+        assert_eq!(map.bricks[1].light_range, 20);
+        assert_eq!(map.bricks[1].light_colour, Color::from(0xFF8000));
+        assert_eq!(map.teams.len(), 1);
+        assert_eq!(map.teams[0].name, "Red");
+        assert_eq!(map.teams[0].colour, Color::from(0xFF0000));
+    }
+
+    #[test]
+    fn bad_lines_fail_the_map() {
+        let bad_environment = HEADER.replace("0.2 0.6 0.2 1", "0.2 green");
+        assert!(load("environment", &bad_environment).is_none());
+
+        let bad_brick = format!("{}0 0 0 4 4 one 1 0 0 1\n", HEADER);
+        assert!(load("brick", &bad_brick).is_none());
+    }
+
+    #[test]
+    fn bad_attributes_are_skipped() {
+        let map = load(
+            "attributes",
+            &format!(
+                "{}0 0 0 4 4 1 1 0 0 1\n\t+ROT sideways\n\t+LIGHT 1 0.5\n\
+                 >TEAM Red\n\t+COLOR 1 red\n",
+                HEADER
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(map.bricks.len(), 1);
+        assert_eq!(map.bricks[0].rotation, 0);
+        assert!(!map.bricks[0].light_enabled);
+        assert_eq!(map.teams[0].colour, Color::from(0xFFFFFF));
+    }
 }
@@ -1,11 +1,11 @@
-use std::{io::Read, num::Wrapping};
+use std::io::Read;
 
 use byteorder::{ByteOrder, LittleEndian};
 use flate2::read::ZlibDecoder;
 
 pub struct Message {
-    message_size: u16,
-    end: u16,
+    message_size: u32,
+    end: u32,
 }
 
 fn read_uint_v(buffer: &[u8]) -> Message {
@@ -15,20 +15,18 @@ fn read_uint_v(buffer: &[u8]) -> Message {
     };
 
     if (buffer[0] & 1) == 1 {
-        msg.message_size = (buffer[0] >> 1) as u16;
+        msg.message_size = (buffer[0] >> 1) as u32;
         msg.end = 1;
-    } else if (buffer[0] & 2) == 1 {
-        msg.message_size = ((LittleEndian::read_u16(buffer) >> 2) + 0x80) as u16;
+    } else if (buffer[0] & 2) != 0 {
+        msg.message_size = ((LittleEndian::read_u16(buffer) >> 2) + 0x80) as u32;
         msg.end = 2;
-    } else if (buffer[0] & 4) == 1 {
-        msg.message_size = ((Wrapping(buffer[2]) << 13)
-            + (Wrapping(buffer[1]) << 5)
-            + (Wrapping(buffer[0]) >> 3)
-            + Wrapping(0x4080 as u16 as u8))
-        .0 as u16;
+    } else if (buffer[0] & 4) != 0 {
+        msg.message_size =
+            (((buffer[2] as u32) << 13) + ((buffer[1] as u32) << 5) + ((buffer[0] as u32) >> 3))
+                + 0x4080;
         msg.end = 3;
     } else {
-        msg.message_size = ((LittleEndian::read_u32(buffer) / 8) + (0x204080 as u32)) as u16;
+        msg.message_size = (LittleEndian::read_u32(buffer) / 8) + 0x204080;
         msg.end = 4;
     }
 
@@ -59,20 +57,62 @@ pub fn new(bytes: Option<[u8; 80]>) -> Buffer {
     }
 }
 
+// Clients only ever send small packets, anything claiming to be bigger is dropped along with
+// the connection rather than buffered
+pub const MAX_MESSAGE_SIZE: u32 = 64 * 1024;
+
+// Takes the first complete message off `pending`, leaving a partial one for the next read
+pub fn next_message(pending: &mut Vec<u8>) -> Result<Option<Buffer>, String> {
+    let Some(first) = pending.first() else {
+        return Ok(None);
+    };
+
+    let header_length = if (first & 1) != 0 {
+        1
+    } else if (first & 2) != 0 {
+        2
+    } else if (first & 4) != 0 {
+        3
+    } else {
+        4
+    };
+    if pending.len() < header_length {
+        return Ok(None);
+    }
+
+    let msg = read_uint_v(pending);
+    if msg.message_size > MAX_MESSAGE_SIZE {
+        return Err(format!("message of {} bytes is too big", msg.message_size));
+    }
+
+    let end = (msg.end + msg.message_size) as usize;
+    if pending.len() < end {
+        return Ok(None);
+    }
+
+    let data = pending[msg.end as usize..end].to_vec();
+    pending.drain(..end);
+
+    return Ok(Some(Buffer {
+        data,
+        uint_length: 0,
+    }));
+}
+
 impl Buffer {
     pub fn zlib_uncompress(&mut self) -> bool {
-        let mut _buffer = [0; 80];
-        let success: bool = match ZlibDecoder::new(&(*self.data)).read(&mut _buffer) {
-            Err(_) => false,
-            Ok(_) => true,
-        };
+        // Capped so a small compressed message can't inflate into a huge one
+        let mut data = vec![];
+        let success: bool = ZlibDecoder::new(&(*self.data))
+            .take(MAX_MESSAGE_SIZE as u64)
+            .read_to_end(&mut data)
+            .is_ok();
         if !success {
             return false;
         };
 
-        if _buffer[0] != 0 {
-            self.data.clear();
-            self.data = _buffer.to_vec();
+        if data.first().is_some_and(|byte| *byte != 0) {
+            self.data = data;
         }
 
         return true;
@@ -95,7 +135,7 @@ impl Buffer {
 
             self.uint_length = 1;
         } else if length < 0x4080 {
-            let mut size = [0 as u8; 2];
+            let mut size = [0_u8; 2];
             let i = ((length - 0x80) << 2) + 2;
             LittleEndian::write_u16(&mut size, i as u16);
 
@@ -105,11 +145,11 @@ impl Buffer {
 
             self.uint_length = 2;
         } else if length < 0x204080 {
-            let mut size = [0 as u8; 3];
+            let mut size = [0_u8; 3];
             let i = ((length - 0x4080) << 3) + 4;
             size[0] = (i & 0xFF) as u8;
-
-            LittleEndian::write_u16(&mut size, (i >> 8) as u16);
+            size[1] = ((i >> 8) & 0xFF) as u8;
+            size[2] = ((i >> 16) & 0xFF) as u8;
 
             for i in 0..3 {
                 self.data.insert(0, size[2 - i]);
@@ -117,7 +157,7 @@ impl Buffer {
 
             self.uint_length = 3;
         } else {
-            let mut size = [0 as u8; 4];
+            let mut size = [0_u8; 4];
             LittleEndian::write_u32(&mut size, ((length - 0x204080) * 8) as u32);
 
             for i in 0..4 {
@@ -145,44 +185,183 @@ impl Buffer {
     pub fn write_uint32(&mut self, uint: u32) {
         let mut buffer: [u8; 4] = [0; 4];
         LittleEndian::write_u32(&mut buffer, uint);
-        for i in 0..4 {
-            self.data.push(buffer[i]);
-        }
+        self.data.extend_from_slice(&buffer);
     }
 
-    pub fn write_float32(&mut self, uint: f32) {
+    pub fn write_int32(&mut self, int: i32) {
         let mut buffer: [u8; 4] = [0; 4];
-        LittleEndian::write_f32(&mut buffer, uint as f32);
-        for i in 0..4 {
-            self.data.push(buffer[i]);
+        LittleEndian::write_i32(&mut buffer, int);
+        self.data.extend_from_slice(&buffer);
+    }
+
+    pub fn read_bool(&mut self) -> Option<bool> {
+        if self.data.is_empty() {
+            return None;
         }
+
+        return Some(self.read_byte() != 0);
     }
 
-    pub fn read_string(&mut self) -> String {
-        let mut new_string = String::new();
-        let mut index = 0;
-
-        for byte in &self.data {
-            index += 1;
-            let char = *byte as char;
-            if char == '\0' {
-                break;
-            }
-            new_string.push(char);
+    pub fn read_uint32(&mut self) -> Option<u32> {
+        if self.data.len() < 4 {
+            return None;
         }
 
-        for _ in 0..index {
-            self.data.remove(0);
+        let uint = LittleEndian::read_u32(&self.data);
+        self.data.drain(..4);
+        return Some(uint);
+    }
+
+    pub fn read_float32(&mut self) -> Option<f32> {
+        if self.data.len() < 4 {
+            return None;
         }
 
+        let float = LittleEndian::read_f32(&self.data);
+        self.data.drain(..4);
+        return Some(float);
+    }
+
+    pub fn write_float32(&mut self, uint: f32) {
+        let mut buffer: [u8; 4] = [0; 4];
+        LittleEndian::write_f32(&mut buffer, uint);
+        self.data.extend_from_slice(&buffer);
+    }
+
+    // Strings are null terminated UTF-8
+    pub fn read_string(&mut self) -> String {
+        let end = match self.data.iter().position(|byte| *byte == 0) {
+            Some(end) => end,
+            None => self.data.len(),
+        };
+
+        let new_string = String::from_utf8_lossy(&self.data[..end]).into_owned();
+        self.data.drain(..(end + 1).min(self.data.len()));
+
         return new_string;
     }
 
     pub fn write_string(&mut self, wrt_string: String) {
-        for char in wrt_string.chars() {
-            let byte = char as u8;
-            self.write_byte(byte);
-        }
+        self.data.extend_from_slice(wrt_string.as_bytes());
         self.write_byte(0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn splits_messages() {
+        // One byte headers holding sizes 2 and 1, then the start of a third message
+        let mut pending = vec![0x05, 1, 2, 0x03, 3, 0x05, 4];
+
+        assert_eq!(next_message(&mut pending).unwrap().unwrap().data, [1, 2]);
+        assert_eq!(next_message(&mut pending).unwrap().unwrap().data, [3]);
+        assert!(next_message(&mut pending).unwrap().is_none());
+        assert_eq!(pending, [0x05, 4]);
+    }
+
+    #[test]
+    fn reads_two_byte_headers() {
+        let mut message = vec![0; 0x80 + 2];
+        message[0] = 0x02;
+
+        let buffer = next_message(&mut message).unwrap().unwrap();
+        assert_eq!(buffer.data.len(), 0x80);
+        assert!(message.is_empty());
+    }
+
+    fn written(length: usize) -> Buffer {
+        let mut buffer = new(None);
+        buffer.data = (0..length).map(|byte| byte as u8).collect();
+        buffer.write_uint_v();
+        return buffer;
+    }
+
+    #[test]
+    fn round_trips_every_header_size() {
+        // Either side of each header size change, up to the biggest message clients may send
+        for (length, header) in [
+            (0, 1),
+            (0x7F, 1),
+            (0x80, 2),
+            (0x407F, 2),
+            (0x4080, 3),
+            (0x4AF1, 3),
+            (MAX_MESSAGE_SIZE as usize, 3),
+        ] {
+            let mut pending = written(length).data;
+            assert_eq!(
+                pending.len(),
+                length + header,
+                "header for {} bytes",
+                length
+            );
+
+            let buffer = next_message(&mut pending).unwrap().unwrap();
+            assert_eq!(buffer.data.len(), length);
+            assert!(buffer
+                .data
+                .iter()
+                .enumerate()
+                .all(|(i, byte)| *byte == i as u8));
+            assert!(pending.is_empty());
+        }
+    }
+
+    #[test]
+    fn round_trips_large_headers() {
+        // Only the server sends messages this big, so they're read without the size limit
+        for (length, header) in [(0x100000, 3), (0x20407F, 3), (0x204080, 4), (0x300000, 4)] {
+            let mut buffer = written(length);
+            assert_eq!(
+                buffer.data.len(),
+                length + header,
+                "header for {} bytes",
+                length
+            );
+
+            buffer.read_uint_v();
+            assert_eq!(buffer.data.len(), length);
+            assert!(next_message(&mut written(length).data).is_err());
+        }
+    }
+
+    fn compress(data: &[u8]) -> Buffer {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+
+        let mut buffer = new(None);
+        buffer.data = encoder.finish().unwrap();
+        return buffer;
+    }
+
+    #[test]
+    fn uncompresses_to_the_exact_length() {
+        let mut buffer = compress(&[6, 1, 0, 1]);
+
+        assert!(buffer.zlib_uncompress());
+        assert_eq!(buffer.data, [6, 1, 0, 1]);
+    }
+
+    #[test]
+    fn leaves_uncompressed_messages_alone() {
+        let mut buffer = new(None);
+        buffer.data = vec![18];
+
+        assert!(!buffer.zlib_uncompress());
+        assert_eq!(buffer.data, [18]);
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        // A four byte header claiming about 32MB
+        let mut pending = vec![0x00, 0x00, 0x00, 0x10];
+        assert!(next_message(&mut pending).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraType {
+    FirstPerson,
+    ThirdPerson,
+    // Circles the target, the player can't turn it
+    Orbit,
+    // Stays at the camera position looking along the camera rotation
+    Fixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraTarget {
+    // The player's own figure
+    Own,
+    Player(u32),
+    Brick(u32),
+}
+
+impl CameraType {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            CameraType::FirstPerson => "first",
+            CameraType::ThirdPerson => "third",
+            CameraType::Orbit => "orbit",
+            CameraType::Fixed => "fixed",
+        };
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{self, Instant};
+
+use crate::{buffer, game};
+
+// Connections that haven't authenticated within `auth_timeout` are dropped
+pub async fn handle(
+    _stream: TcpStream,
+    events: mpsc::Sender<game::Event>,
+    auth_timeout: Duration,
+) -> Result<(), ()> {
+    let mut data = [0_u8; 1024];
+    let mut pending: Vec<u8> = vec![];
+
+    let (mut reader, writer) = _stream.into_split();
+    let mut writer = Some(writer);
+
+    let disconnected = Arc::new(Notify::new());
+    let mut net_id: Option<u32> = None;
+    let auth_deadline = Instant::now() + auth_timeout;
+
+    'read: loop {
+        let result = tokio::select! {
+            result = reader.read(&mut data) => result,
+            _ = disconnected.notified() => break,
+            _ = time::sleep_until(auth_deadline), if net_id.is_none() => {
+                println!("Client never authenticated");
+                break;
+            }
+        };
+
+        let size = match result {
+            Ok(0) | Err(_) => break,
+            Ok(size) => size,
+        };
+        pending.extend_from_slice(&data[..size]);
+
+        loop {
+            let mut buffer = match buffer::next_message(&mut pending) {
+                Ok(Some(buffer)) => buffer,
+                Ok(None) => break,
+                Err(e) => {
+                    println!("Dropping client: {}", e);
+                    break 'read;
+                }
+            };
+
+            buffer.zlib_uncompress();
+
+            if buffer.data.is_empty() {
+                continue;
+            }
+
+            let packet_type = buffer.read_byte();
+
+            if packet_type == 1 {
+                let Some(stream) = writer.take() else {
+                    // Already authenticated on this connection
+                    continue;
+                };
+
+                let (reply, assigned) = oneshot::channel();
+                let event = game::Event::Join {
+                    stream,
+                    disconnected: disconnected.clone(),
+                    buffer,
+                    net_id: reply,
+                };
+                if events.send(event).await.is_err() {
+                    break 'read;
+                }
+
+                match assigned.await {
+                    Ok(id) => net_id = Some(id),
+                    Err(_) => break 'read,
+                }
+                continue;
+            }
+
+            // Nothing but authentication is accepted until the player has joined
+            let Some(net_id) = net_id else {
+                continue;
+            };
+
+            let event = game::Event::Packet {
+                net_id,
+                packet_type,
+                buffer,
+            };
+            if events.send(event).await.is_err() {
+                break 'read;
+            }
+        }
+    }
+
+    if let Some(net_id) = net_id {
+        let _ = events.send(game::Event::Leave { net_id }).await;
+    }
+
+    println!("Client has disconnected");
+    Ok(())
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+
+use crate::brick::{Brick, Vector3};
+
+const CELL_SIZE: f32 = 16.0;
+// Bricks spanning more cells than this (baseplates) are checked on every query instead
+const MAX_CELLS: usize = 512;
+
+// Players are treated as a box this wide and tall, standing on their position
+pub const PLAYER_WIDTH: f32 = 2.0;
+pub const PLAYER_HEIGHT: f32 = 5.0;
+
+// Rays stop here however far they were asked to go
+pub const MAX_RAY_DISTANCE: f32 = 10_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Aabb {
+    // Boxes that only share a face count as touching
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        return self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z;
+    }
+
+    // Distance from `point` to the closest point on the box, 0 inside it
+    pub fn distance_to(&self, point: Vector3) -> f32 {
+        let x = point.x.clamp(self.min.x, self.max.x) - point.x;
+        let y = point.y.clamp(self.min.y, self.max.y) - point.y;
+        let z = point.z.clamp(self.min.z, self.max.z) - point.z;
+
+        return (x * x + y * y + z * z).sqrt();
+    }
+
+    // How far along the ray it enters the box, rays starting inside a box never hit it.
+    // `inverse` is 1 / direction on each axis.
+    pub fn ray_distance(&self, origin: Vector3, inverse: Vector3) -> Option<f32> {
+        let mut near = f32::NEG_INFINITY;
+        let mut far = f32::INFINITY;
+
+        for (origin, inverse, min, max) in [
+            (origin.x, inverse.x, self.min.x, self.max.x),
+            (origin.y, inverse.y, self.min.y, self.max.y),
+            (origin.z, inverse.z, self.min.z, self.max.z),
+        ] {
+            if inverse.is_infinite() {
+                // Parallel to this axis, so it has to already be between the sides
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let a = (min - origin) * inverse;
+            let b = (max - origin) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        if near > far || near < 0.0 {
+            return None;
+        }
+
+        return Some(near);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Brick(u32),
+    Player(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub target: Target,
+    pub position: Vector3,
+    pub distance: f32,
+}
+
+// Bricks rotate about their centre around the vertical axis, so the box grows to fit the turn
+pub fn brick_bounds(brick: &Brick) -> Aabb {
+    let centre = Vector3 {
+        x: brick.position.x + brick.scale.x / 2.0,
+        y: brick.position.y + brick.scale.y / 2.0,
+        z: brick.position.z + brick.scale.z / 2.0,
+    };
+
+    let angle = (brick.rotation as f32).to_radians();
+    let (sin, cos) = (angle.sin().abs(), angle.cos().abs());
+    let half = Vector3 {
+        x: (cos * brick.scale.x + sin * brick.scale.y) / 2.0,
+        y: (sin * brick.scale.x + cos * brick.scale.y) / 2.0,
+        z: brick.scale.z / 2.0,
+    };
+
+    return Aabb {
+        min: Vector3 {
+            x: centre.x - half.x,
+            y: centre.y - half.y,
+            z: centre.z - half.z,
+        },
+        max: Vector3 {
+            x: centre.x + half.x,
+            y: centre.y + half.y,
+            z: centre.z + half.z,
+        },
+    };
+}
+
+pub fn player_bounds(position: Vector3) -> Aabb {
+    return Aabb {
+        min: Vector3 {
+            x: position.x - PLAYER_WIDTH / 2.0,
+            y: position.y - PLAYER_WIDTH / 2.0,
+            z: position.z,
+        },
+        max: Vector3 {
+            x: position.x + PLAYER_WIDTH / 2.0,
+            y: position.y + PLAYER_WIDTH / 2.0,
+            z: position.z + PLAYER_HEIGHT,
+        },
+    };
+}
+
+fn cell(value: f32) -> i32 {
+    return (value / CELL_SIZE).floor() as i32;
+}
+
+// Uniform grid over the collidable bricks, holding indices into the brick list it was built from
+#[derive(Default)]
+pub struct Grid {
+    pub bounds: Vec<Aabb>,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+    large: Vec<usize>,
+    // Corners of the cells in use, rays stop walking once they leave them
+    min_cell: [i32; 3],
+    max_cell: [i32; 3],
+}
+
+pub fn new(bricks: &[Brick]) -> Grid {
+    let mut grid = Grid {
+        min_cell: [i32::MAX; 3],
+        max_cell: [i32::MIN; 3],
+        ..Grid::default()
+    };
+
+    for (index, brick) in bricks.iter().enumerate() {
+        let bounds = brick_bounds(brick);
+        grid.bounds.push(bounds);
+
+        if !brick.collision {
+            continue;
+        }
+
+        let (min_x, max_x) = (cell(bounds.min.x), cell(bounds.max.x));
+        let (min_y, max_y) = (cell(bounds.min.y), cell(bounds.max.y));
+        let (min_z, max_z) = (cell(bounds.min.z), cell(bounds.max.z));
+
+        let size = (max_x - min_x + 1) as usize
+            * (max_y - min_y + 1) as usize
+            * (max_z - min_z + 1) as usize;
+        if size > MAX_CELLS {
+            grid.large.push(index);
+            continue;
+        }
+
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                for z in min_z..=max_z {
+                    grid.cells.entry((x, y, z)).or_default().push(index);
+                }
+            }
+        }
+
+        let (min, max) = ([min_x, min_y, min_z], [max_x, max_y, max_z]);
+        for axis in 0..3 {
+            grid.min_cell[axis] = grid.min_cell[axis].min(min[axis]);
+            grid.max_cell[axis] = grid.max_cell[axis].max(max[axis]);
+        }
+    }
+
+    return grid;
+}
+
+impl Grid {
+    // Indices of every collidable brick overlapping `bounds`, in brick order
+    pub fn query(&self, bounds: &Aabb) -> Vec<usize> {
+        let mut found: Vec<usize> = vec![];
+
+        for x in cell(bounds.min.x)..=cell(bounds.max.x) {
+            for y in cell(bounds.min.y)..=cell(bounds.max.y) {
+                for z in cell(bounds.min.z)..=cell(bounds.max.z) {
+                    if let Some(indices) = self.cells.get(&(x, y, z)) {
+                        found.extend_from_slice(indices);
+                    }
+                }
+            }
+        }
+        found.extend_from_slice(&self.large);
+
+        found.sort_unstable();
+        found.dedup();
+        found.retain(|index| self.bounds[*index].overlaps(bounds));
+
+        return found;
+    }
+
+    // Walks the cells along the ray in order, returning the index and distance of the first
+    // collidable brick hit. `direction` has to be normalised.
+    pub fn raycast(
+        &self,
+        origin: Vector3,
+        direction: Vector3,
+        max_distance: f32,
+    ) -> Option<(usize, f32)> {
+        if !origin.is_finite() || !direction.is_finite() {
+            return None;
+        }
+        let max_distance = max_distance.min(MAX_RAY_DISTANCE);
+
+        let inverse = Vector3 {
+            x: 1.0 / direction.x,
+            y: 1.0 / direction.y,
+            z: 1.0 / direction.z,
+        };
+
+        let mut closest: Option<(usize, f32)> = None;
+        let check = |index: usize, closest: &mut Option<(usize, f32)>| {
+            let Some(distance) = self.bounds[index].ray_distance(origin, inverse) else {
+                return;
+            };
+            if distance > max_distance {
+                return;
+            }
+            if closest.is_none_or(|(_, best)| distance < best) {
+                *closest = Some((index, distance));
+            }
+        };
+
+        for index in &self.large {
+            check(*index, &mut closest);
+        }
+        if self.cells.is_empty() {
+            return closest;
+        }
+
+        let mut position = [cell(origin.x), cell(origin.y), cell(origin.z)];
+        let origin_axes = [origin.x, origin.y, origin.z];
+        let inverse_axes = [inverse.x, inverse.y, inverse.z];
+
+        // Distance along the ray to the next cell boundary on each axis, and between boundaries
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        let mut step = [0; 3];
+        for axis in 0..3 {
+            if inverse_axes[axis].is_infinite() {
+                continue;
+            }
+
+            step[axis] = if inverse_axes[axis] > 0.0 { 1 } else { -1 };
+            let boundary = (position[axis] + (step[axis] + 1) / 2) as f32 * CELL_SIZE;
+            next[axis] = (boundary - origin_axes[axis]) * inverse_axes[axis];
+            delta[axis] = CELL_SIZE * inverse_axes[axis].abs();
+        }
+
+        loop {
+            // Heading away from every brick in the grid, so there's nothing left to hit
+            let leaving = (0..3).any(|axis| {
+                (position[axis] < self.min_cell[axis] && step[axis] <= 0)
+                    || (position[axis] > self.max_cell[axis] && step[axis] >= 0)
+            });
+            if leaving {
+                break;
+            }
+
+            if let Some(indices) = self.cells.get(&(position[0], position[1], position[2])) {
+                for index in indices {
+                    check(*index, &mut closest);
+                }
+            }
+
+            let axis = if next[0] <= next[1] && next[0] <= next[2] {
+                0
+            } else if next[1] <= next[2] {
+                1
+            } else {
+                2
+            };
+
+            // Anything hit before leaving this cell can't be beaten by a later one
+            let exit = next[axis];
+            if closest.is_some_and(|(_, distance)| distance <= exit) {
+                break;
+            }
+            if exit > max_distance {
+                break;
+            }
+
+            position[axis] += step[axis];
+            next[axis] += delta[axis];
+        }
+
+        return closest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn vector(x: f32, y: f32, z: f32) -> Vector3 {
+        return Vector3 { x, y, z };
+    }
+
+    fn aabb(min: (f32, f32, f32), max: (f32, f32, f32)) -> Aabb {
+        return Aabb {
+            min: vector(min.0, min.1, min.2),
+            max: vector(max.0, max.1, max.2),
+        };
+    }
+
+    fn brick(position: Vector3, scale: Vector3, rotation: i32) -> Brick {
+        return Brick {
+            position,
+            scale,
+            rotation,
+            collision: true,
+            ..Brick::default()
+        };
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        return (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4 && (a.z - b.z).abs() < 1e-4;
+    }
+
+    #[test]
+    fn overlaps() {
+        let a = aabb((0.0, 0.0, 0.0), (2.0, 2.0, 2.0));
+
+        assert!(a.overlaps(&aabb((1.0, 1.0, 1.0), (3.0, 3.0, 3.0))));
+        assert!(a.overlaps(&aabb((0.5, 0.5, 0.5), (1.5, 1.5, 1.5))));
+        // Sharing a face is enough
+        assert!(a.overlaps(&aabb((2.0, 0.0, 0.0), (4.0, 2.0, 2.0))));
+        assert!(!a.overlaps(&aabb((2.1, 0.0, 0.0), (4.0, 2.0, 2.0))));
+        assert!(!a.overlaps(&aabb((0.0, 0.0, 3.0), (2.0, 2.0, 4.0))));
+    }
+
+    #[test]
+    fn unrotated_brick_bounds() {
+        let bounds = brick_bounds(&brick(vector(1.0, 2.0, 3.0), vector(4.0, 2.0, 1.0), 0));
+
+        assert!(close(bounds.min, vector(1.0, 2.0, 3.0)));
+        assert!(close(bounds.max, vector(5.0, 4.0, 4.0)));
+    }
+
+    #[test]
+    fn rotated_brick_bounds() {
+        // A quarter turn swaps the width and depth around the centre at (2, 1)
+        let bounds = brick_bounds(&brick(vector(0.0, 0.0, 0.0), vector(4.0, 2.0, 1.0), 90));
+        assert!(close(bounds.min, vector(1.0, -1.0, 0.0)));
+        assert!(close(bounds.max, vector(3.0, 3.0, 1.0)));
+
+        // An eighth of a turn grows a 2x2 square to fit its diagonal
+        let bounds = brick_bounds(&brick(vector(0.0, 0.0, 0.0), vector(2.0, 2.0, 1.0), 45));
+        let half = 2.0_f32.sqrt();
+        assert!(close(bounds.min, vector(1.0 - half, 1.0 - half, 0.0)));
+        assert!(close(bounds.max, vector(1.0 + half, 1.0 + half, 1.0)));
+    }
+
+    #[test]
+    fn query_skips_bricks_without_collision() {
+        let mut bricks = vec![
+            brick(vector(0.0, 0.0, 0.0), vector(4.0, 4.0, 1.0), 0),
+            brick(vector(0.0, 0.0, 0.0), vector(4.0, 4.0, 1.0), 0),
+            brick(vector(40.0, 0.0, 0.0), vector(4.0, 4.0, 1.0), 0),
+            // Big enough to go in the large list
+            brick(vector(-500.0, -500.0, -2.0), vector(1000.0, 1000.0, 1.0), 0),
+        ];
+        bricks[1].collision = false;
+
+        let grid = new(&bricks);
+        assert_eq!(grid.query(&player_bounds(vector(2.0, 2.0, 1.0))), [0]);
+        assert_eq!(grid.query(&player_bounds(vector(100.0, 100.0, -3.0))), [3]);
+    }
+
+    #[test]
+    fn raycast_finds_the_nearest_brick() {
+        let bricks = vec![
+            brick(vector(20.0, -1.0, 0.0), vector(2.0, 2.0, 2.0), 0),
+            brick(vector(10.0, -1.0, 0.0), vector(2.0, 2.0, 2.0), 0),
+        ];
+        let grid = new(&bricks);
+
+        let hit = grid.raycast(vector(0.0, 0.0, 1.0), vector(1.0, 0.0, 0.0), 100.0);
+        assert_eq!(hit, Some((1, 10.0)));
+        assert_eq!(
+            grid.raycast(vector(0.0, 0.0, 1.0), vector(1.0, 0.0, 0.0), 5.0),
+            None
+        );
+        assert_eq!(
+            grid.raycast(vector(0.0, 0.0, 1.0), vector(-1.0, 0.0, 0.0), 100.0),
+            None
+        );
+    }
+
+    #[test]
+    fn raycast_ends_on_bad_input() {
+        let bricks = vec![brick(vector(10.0, -1.0, 0.0), vector(2.0, 2.0, 2.0), 0)];
+        let grid = new(&bricks);
+        let right = vector(1.0, 0.0, 0.0);
+
+        // Misses going on forever, from inside and outside the grid
+        let up = vector(0.0, 0.0, 1.0);
+        assert_eq!(grid.raycast(vector(0.0, 0.0, 1.0), up, f32::INFINITY), None);
+        assert_eq!(
+            grid.raycast(
+                vector(-1e6, 0.0, 1.0),
+                vector(-1.0, 0.0, 0.0),
+                f32::INFINITY
+            ),
+            None
+        );
+        assert_eq!(
+            grid.raycast(vector(0.0, 0.0, 1.0), right, f32::INFINITY),
+            Some((0, 10.0))
+        );
+
+        let nan = vector(f32::NAN, 0.0, 1.0);
+        assert_eq!(grid.raycast(nan, right, 100.0), None);
+        assert_eq!(grid.raycast(vector(0.0, 0.0, 1.0), nan, 100.0), None);
+
+        // Far past the cap
+        let far = vector(MAX_RAY_DISTANCE * 2.0, -1.0, 0.0);
+        let grid = new(&[brick(far, vector(2.0, 2.0, 2.0), 0)]);
+        assert_eq!(
+            grid.raycast(vector(0.0, 0.0, 1.0), right, f32::INFINITY),
+            None
+        );
+    }
+
+    // 50k bricks laid out like a big map
+    fn big_map() -> Vec<Brick> {
+        let mut bricks = Vec::with_capacity(50_000);
+        for index in 0..50_000 {
+            let (x, y) = ((index % 250) as f32 * 4.0, (index / 250) as f32 * 4.0);
+            bricks.push(brick(
+                vector(x, y, (index % 7) as f32),
+                vector(3.0, 3.0, 1.0),
+                (index % 4) * 45,
+            ));
+        }
+        return bricks;
+    }
+
+    // Player positions spread over the map, the same every run
+    fn positions(count: usize) -> Vec<Vector3> {
+        let mut seed: u32 = 1;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        return (0..count)
+            .map(|_| vector(next() * 1000.0, next() * 800.0, next() * 8.0))
+            .collect();
+    }
+
+    #[test]
+    fn queries_match_checking_every_brick() {
+        let bricks = big_map();
+        let grid = new(&bricks);
+
+        let mut found = 0;
+        for position in positions(100) {
+            let bounds = player_bounds(position);
+            let expected: Vec<usize> = (0..bricks.len())
+                .filter(|index| brick_bounds(&bricks[*index]).overlaps(&bounds))
+                .collect();
+
+            let touching = grid.query(&bounds);
+            assert_eq!(touching, expected);
+            found += touching.len();
+        }
+        assert!(found > 0);
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_
+    #[test]
+    #[ignore]
+    fn bench_50k_bricks() {
+        let bricks = big_map();
+
+        let start = Instant::now();
+        let grid = new(&bricks);
+        println!(
+            "built grid of {} bricks in {:?}",
+            bricks.len(),
+            start.elapsed()
+        );
+
+        let positions = positions(100_000);
+        let start = Instant::now();
+        let found: usize = positions
+            .iter()
+            .map(|position| grid.query(&player_bounds(*position)).len())
+            .sum();
+        let elapsed = start.elapsed();
+        println!(
+            "{} queries in {:?}, {:?} each, {} bricks found",
+            positions.len(),
+            elapsed,
+            elapsed / positions.len() as u32,
+            found
+        );
+
+        let start = Instant::now();
+        let hits = positions
+            .iter()
+            .filter_map(|position| grid.raycast(*position, vector(0.6, 0.8, 0.0), 500.0))
+            .count();
+        let elapsed = start.elapsed();
+        println!(
+            "{} raycasts in {:?}, {:?} each, {} hits",
+            positions.len(),
+            elapsed,
+            elapsed / positions.len() as u32,
+            hits
+        );
+    }
+}
@@ -1,17 +1,50 @@
 // https://docs.rs/hex-rgb/latest/src/hex_rgb/lib.rs.html
+use std::fmt;
 use std::num::ParseIntError;
+use std::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
+    // 255 is fully opaque
+    pub alpha: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColourError {
+    Empty,
+    // Hex codes are 3, 4, 6 or 8 digits long
+    InvalidLength(usize),
+    InvalidHex,
+    UnknownName(String),
+}
+
+impl fmt::Display for ColourError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColourError::Empty => write!(f, "empty colour"),
+            ColourError::InvalidLength(length) => {
+                write!(f, "hex colours can't be {} digits long", length)
+            }
+            ColourError::InvalidHex => write!(f, "invalid hex digits"),
+            ColourError::UnknownName(name) => write!(f, "unknown colour name {}", name),
+        }
+    }
+}
+
+impl Default for Color {
+    fn default() -> Color {
+        Color::from(0x000000)
+    }
 }
 
 impl Color {
-    pub fn new(hex_code: &str) -> Result<Color, String> {
+    // Accepts #RGB, #RGBA, #RRGGBB and #RRGGBBAA, the # is optional
+    pub fn new(hex_code: &str) -> Result<Color, ColourError> {
         if hex_code.is_empty() {
-            return Err("empty".to_string());
+            return Err(ColourError::Empty);
         }
 
         // remove # from hex_code
@@ -21,38 +54,216 @@ impl Color {
             hex_code
         };
 
-        // convert shorthand RGB hexcode to RRGGBB
-        let hex_code = if hex_code.len() == 3 {
-            repeat_letters(hex_code, 1)
-        } else {
-            hex_code.to_owned()
-        };
-
-        if hex_code.len() % 2 != 0 {
-            return Err("invalid".to_string());
+        if !hex_code.chars().all(|char| char.is_ascii_hexdigit()) {
+            return Err(ColourError::InvalidHex);
         }
 
-        let decoded_values = decode_hex(&hex_code).unwrap_or_default();
-        if decoded_values.is_empty() || decoded_values.len() > 4 {
-            return Err("invalid".to_string());
-        }
+        // convert shorthand RGB(A) hexcode to RRGGBB(AA)
+        let hex_code = match hex_code.len() {
+            3 | 4 => repeat_letters(hex_code, 1),
+            6 | 8 => hex_code.to_owned(),
+            length => return Err(ColourError::InvalidLength(length)),
+        };
+
+        let decoded_values = decode_hex(&hex_code).map_err(|_| ColourError::InvalidHex)?;
 
         let color = Color {
             red: decoded_values[0],
             green: decoded_values[1],
             blue: decoded_values[2],
+            alpha: decoded_values.get(3).copied().unwrap_or(255),
         };
 
         Ok(color)
     }
+
+    pub fn from_rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Color {
+        Color {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    // BRK files store colours as 0-1 floats
+    pub fn from_rgb_f32(red: f32, green: f32, blue: f32) -> Color {
+        Color::from_rgba(channel(red), channel(green), channel(blue), 255)
+    }
+
+    // CSS colour names, case insensitive
+    pub fn from_name(name: &str) -> Result<Color, ColourError> {
+        let name = name.trim().to_ascii_lowercase();
+        if name == "transparent" {
+            return Ok(Color::from_rgba(0, 0, 0, 0));
+        }
+
+        match CSS_COLOURS.iter().find(|(css_name, _)| *css_name == name) {
+            Some((_, rgb)) => Ok(Color::from(*rgb)),
+            None => Err(ColourError::UnknownName(name)),
+        }
+    }
+
+    // Hue in degrees, saturation and lightness from 0 to 1
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Color {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let lightness = lightness.clamp(0.0, 1.0);
+
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        from_chroma(hue, chroma, lightness - chroma / 2.0)
+    }
+
+    // Hue in degrees, saturation and value from 0 to 1
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Color {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+
+        let chroma = value * saturation;
+        from_chroma(hue, chroma, value - chroma)
+    }
+
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let lightness = (max + min) / 2.0;
+
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+
+        (hue, saturation, lightness)
+    }
+
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let saturation = if max == 0.0 { 0.0 } else { (max - min) / max };
+
+        (hue, saturation, max)
+    }
+
+    // Hue in degrees along with the largest and smallest channels, as 0-1 floats
+    fn hue(&self) -> (f32, f32, f32) {
+        let red = self.red as f32 / 255.0;
+        let green = self.green as f32 / 255.0;
+        let blue = self.blue as f32 / 255.0;
+
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == red {
+            60.0 * ((green - blue) / delta).rem_euclid(6.0)
+        } else if max == green {
+            60.0 * ((blue - red) / delta + 2.0)
+        } else {
+            60.0 * ((red - green) / delta + 4.0)
+        };
+
+        (hue, max, min)
+    }
+
+    // Part way from this colour to `other`, `amount` 0 being this one and 1 being `other`
+    pub fn lerp(self, other: Color, amount: f32) -> Color {
+        let amount = amount.clamp(0.0, 1.0);
+        let mix =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+
+        Color {
+            red: mix(self.red, other.red),
+            green: mix(self.green, other.green),
+            blue: mix(self.blue, other.blue),
+            alpha: mix(self.alpha, other.alpha),
+        }
+    }
+
+    // Lays `top` over this colour, letting this one show through as much as `top` is transparent
+    pub fn blend(self, top: Color) -> Color {
+        let top_alpha = top.alpha as f32 / 255.0;
+        let bottom_alpha = self.alpha as f32 / 255.0 * (1.0 - top_alpha);
+        let alpha = top_alpha + bottom_alpha;
+        if alpha == 0.0 {
+            return Color::from_rgba(0, 0, 0, 0);
+        }
+
+        let mix = |bottom: u8, top: u8| {
+            ((top as f32 * top_alpha + bottom as f32 * bottom_alpha) / alpha).round() as u8
+        };
+
+        Color {
+            red: mix(self.red, top.red),
+            green: mix(self.green, top.green),
+            blue: mix(self.blue, top.blue),
+            alpha: channel(alpha),
+        }
+    }
+
+    // Colours go over the wire as 0xBBGGRR, the client has no alpha
+    pub fn into_bgr_u32(self) -> u32 {
+        self.red as u32 | (self.green as u32) << 8 | (self.blue as u32) << 16
+    }
+}
+
+// Reads 0xRRGGBB, the way colours are written as hex literals
+impl From<u32> for Color {
+    fn from(rgb: u32) -> Color {
+        Color {
+            red: (rgb >> 16) as u8,
+            green: (rgb >> 8) as u8,
+            blue: rgb as u8,
+            alpha: 255,
+        }
+    }
+}
+
+// Takes hex codes or CSS colour names
+impl FromStr for Color {
+    type Err = ColourError;
+
+    fn from_str(colour: &str) -> Result<Color, ColourError> {
+        match Color::new(colour) {
+            Err(ColourError::InvalidHex) if !colour.starts_with('#') => Color::from_name(colour),
+            result => result,
+        }
+    }
 }
 
-impl PartialEq for Color {
-    fn eq(&self, other: &Color) -> bool {
-        self.red == other.red && self.green == other.green && self.blue == other.blue
+// RRGGBB with AA on the end unless it's opaque. Chat can't take alpha, so it uses
+// message::colour_code instead.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}{:02X}{:02X}", self.red, self.green, self.blue)?;
+        if self.alpha != 255 {
+            write!(f, "{:02X}", self.alpha)?;
+        }
+
+        Ok(())
     }
 }
 
+fn channel(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Shared end of the HSL and HSV conversions, `offset` lifts every channel to the right lightness
+fn from_chroma(hue: f32, chroma: f32, offset: f32) -> Color {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let second = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+
+    let (red, green, blue) = match hue as u32 {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+
+    Color::from_rgb_f32(red + offset, green + offset, blue + offset)
+}
+
 fn crop_letters(s: &str, pos: usize) -> &str {
     match s.char_indices().nth(pos) {
         Some((pos, _)) => &s[pos..],
@@ -78,9 +289,273 @@ fn decode_hex(s: &str) -> Result<Vec<u8>, ParseIntError> {
         .collect()
 }
 
-pub fn convert_hexcode_to_rgb(hex_code: String) -> Result<Color, String> {
-    match Color::new(&hex_code) {
-        Ok(color) => Ok(color),
-        Err(e) => Err(e),
+pub fn convert_hexcode_to_rgb(hex_code: String) -> Result<Color, ColourError> {
+    Color::new(&hex_code)
+}
+
+const CSS_COLOURS: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn parses_hex_lengths() {
+        let opaque = Color::from_rgba(0x11, 0x22, 0x33, 255);
+        assert_eq!(Color::new("#123"), Ok(opaque));
+        assert_eq!(Color::new("112233"), Ok(opaque));
+        assert_eq!(
+            Color::new("#1234"),
+            Ok(Color::from_rgba(0x11, 0x22, 0x33, 0x44))
+        );
+        assert_eq!(
+            Color::new("#11223344"),
+            Ok(Color::from_rgba(0x11, 0x22, 0x33, 0x44))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Color::new(""), Err(ColourError::Empty));
+        assert_eq!(Color::new("#12345"), Err(ColourError::InvalidLength(5)));
+        assert_eq!(Color::new("#"), Err(ColourError::InvalidLength(0)));
+        assert_eq!(Color::new("#12G"), Err(ColourError::InvalidHex));
+        assert_eq!(Color::new("#ééé"), Err(ColourError::InvalidHex));
+        assert_eq!(
+            "notacolour".parse::<Color>(),
+            Err(ColourError::UnknownName("notacolour".to_string()))
+        );
+        assert_eq!("#notacolour".parse::<Color>(), Err(ColourError::InvalidHex));
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("RebeccaPurple".parse(), Ok(Color::from(0x663399)));
+        assert_eq!("fff".parse(), Ok(Color::from(0xFFFFFF)));
+        assert_eq!(
+            Color::from_name("transparent"),
+            Ok(Color::from_rgba(0, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn displays_alpha_only_when_transparent() {
+        assert_eq!(Color::from(0x0A0B0C).to_string(), "0A0B0C");
+        assert_eq!(Color::from_rgba(10, 11, 12, 128).to_string(), "0A0B0C80");
+    }
+
+    #[test]
+    fn hsl_round_trips() {
+        for rgb in [
+            0xFF0000, 0x00FF00, 0x0000FF, 0x663399, 0xF0F8FF, 0x808080, 0x000000,
+        ] {
+            let colour = Color::from(rgb);
+            let (hue, saturation, lightness) = colour.to_hsl();
+            assert_eq!(Color::from_hsl(hue, saturation, lightness), colour);
+        }
+
+        let (hue, saturation, lightness) = Color::from(0x663399).to_hsl();
+        assert!(close(hue, 270.0) && close(saturation, 0.5) && close(lightness, 0.4));
+        assert_eq!(Color::from_hsl(-240.0, 1.0, 0.5), Color::from(0x00FF00));
+    }
+
+    #[test]
+    fn hsv_round_trips() {
+        for rgb in [
+            0xFF0000, 0x00FF00, 0x0000FF, 0x663399, 0xF0F8FF, 0x808080, 0x000000,
+        ] {
+            let colour = Color::from(rgb);
+            let (hue, saturation, value) = colour.to_hsv();
+            assert_eq!(Color::from_hsv(hue, saturation, value), colour);
+        }
+
+        let (hue, saturation, value) = Color::from(0x663399).to_hsv();
+        assert!(close(hue, 270.0) && close(saturation, 0.667) && close(value, 0.6));
+    }
+
+    #[test]
+    fn lerps() {
+        let black = Color::from_rgba(0, 0, 0, 0);
+        let white = Color::from(0xFFFFFF);
+
+        assert_eq!(black.lerp(white, 0.0), black);
+        assert_eq!(black.lerp(white, 1.0), white);
+        assert_eq!(black.lerp(white, 0.5), Color::from_rgba(128, 128, 128, 128));
+        assert_eq!(black.lerp(white, 2.0), white);
+    }
+
+    #[test]
+    fn blends() {
+        let red = Color::from(0xFF0000);
+        let blue = Color::from(0x0000FF);
+
+        assert_eq!(red.blend(blue), blue);
+        assert_eq!(red.blend(Color::from_rgba(0, 0, 255, 0)), red);
+        assert_eq!(
+            red.blend(Color::from_rgba(0, 0, 255, 128)),
+            Color::from_rgba(127, 0, 128, 255)
+        );
+        assert_eq!(
+            Color::from_rgba(0, 0, 0, 0).blend(Color::from_rgba(0, 0, 0, 0)),
+            Color::from_rgba(0, 0, 0, 0)
+        );
+        // Half over nothing stays half transparent
+        assert_eq!(
+            Color::from_rgba(0, 0, 0, 0).blend(Color::from_rgba(0, 0, 255, 128)),
+            Color::from_rgba(0, 0, 255, 128)
+        );
     }
 }
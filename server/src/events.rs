@@ -0,0 +1,381 @@
+use crate::{brick::Vector3, collision::Hit, game::Game};
+
+// Handlers run in the order they were registered. Setting `cancelled` on an event
+// stops the remaining handlers and whatever the game would have done with it.
+pub type Handler<E> = Box<dyn FnMut(&mut Game, &mut E) + Send>;
+
+pub struct PlayerJoin {
+    pub net_id: u32,
+}
+
+pub struct PlayerLeave {
+    pub net_id: u32,
+}
+
+pub struct Chat {
+    pub net_id: u32,
+    pub message: String,
+    pub cancelled: bool,
+}
+
+// Any chat command other than "chat", e.g. "/kick Player" arrives as command "kick", args "Player"
+pub struct Command {
+    pub net_id: u32,
+    pub command: String,
+    pub args: String,
+    pub cancelled: bool,
+}
+
+pub struct Spawn {
+    pub net_id: u32,
+    pub position: Vector3,
+    pub cancelled: bool,
+}
+
+pub struct Death {
+    pub net_id: u32,
+    pub cancelled: bool,
+}
+
+pub struct BrickTouched {
+    pub net_id: u32,
+    pub brick: u32,
+    pub cancelled: bool,
+}
+
+pub struct BrickTouchEnded {
+    pub net_id: u32,
+    pub brick: u32,
+}
+
+// A player walked into a bot
+pub struct BotTouched {
+    pub net_id: u32,
+    pub bot: u32,
+    pub cancelled: bool,
+}
+
+pub struct BrickClicked {
+    pub net_id: u32,
+    pub brick: u32,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    KeyDown(String),
+    KeyUp(String),
+    // `position` is where the click ray starts, the player's own position when the client sent no ray
+    Click {
+        position: Vector3,
+        direction: Option<Vector3>,
+    },
+}
+
+// Raw input straight off the wire, cancelling it stops the key and tool events it would cause
+pub struct PlayerInput {
+    pub net_id: u32,
+    pub input: Input,
+    pub cancelled: bool,
+}
+
+// `key` has already been through the player's key bindings
+pub struct KeyPress {
+    pub net_id: u32,
+    pub key: String,
+    pub cancelled: bool,
+}
+
+pub struct KeyRelease {
+    pub net_id: u32,
+    pub key: String,
+}
+
+pub struct ToolActivated {
+    pub net_id: u32,
+    pub tool: u32,
+    pub cancelled: bool,
+}
+
+// A projectile fired from `tool` hit a brick or player
+pub struct ProjectileHit {
+    pub net_id: u32,
+    pub tool: u32,
+    pub hit: Hit,
+    pub cancelled: bool,
+}
+
+pub struct ToolEquipped {
+    pub net_id: u32,
+    pub tool: u32,
+    pub cancelled: bool,
+}
+
+// Anything that can stop the handlers after it
+pub trait Cancellable {
+    fn cancelled(&self) -> bool;
+}
+
+impl Cancellable for PlayerJoin {
+    fn cancelled(&self) -> bool {
+        return false;
+    }
+}
+
+impl Cancellable for PlayerLeave {
+    fn cancelled(&self) -> bool {
+        return false;
+    }
+}
+
+impl Cancellable for Chat {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for Command {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for Spawn {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for Death {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for BrickTouched {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for BrickTouchEnded {
+    fn cancelled(&self) -> bool {
+        return false;
+    }
+}
+
+impl Cancellable for BotTouched {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for BrickClicked {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for PlayerInput {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for KeyRelease {
+    fn cancelled(&self) -> bool {
+        return false;
+    }
+}
+
+impl Cancellable for KeyPress {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for ToolActivated {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for ProjectileHit {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+impl Cancellable for ToolEquipped {
+    fn cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+#[derive(Default)]
+pub struct Events {
+    pub player_join: Vec<Handler<PlayerJoin>>,
+    pub player_leave: Vec<Handler<PlayerLeave>>,
+    pub chat: Vec<Handler<Chat>>,
+    pub command: Vec<Handler<Command>>,
+    pub spawn: Vec<Handler<Spawn>>,
+    pub death: Vec<Handler<Death>>,
+    pub brick_touched: Vec<Handler<BrickTouched>>,
+    pub brick_touch_ended: Vec<Handler<BrickTouchEnded>>,
+    pub bot_touched: Vec<Handler<BotTouched>>,
+    pub brick_clicked: Vec<Handler<BrickClicked>>,
+    pub player_input: Vec<Handler<PlayerInput>>,
+    pub key_press: Vec<Handler<KeyPress>>,
+    pub key_release: Vec<Handler<KeyRelease>>,
+    pub tool_activated: Vec<Handler<ToolActivated>>,
+    pub tool_equipped: Vec<Handler<ToolEquipped>>,
+    pub projectile_hit: Vec<Handler<ProjectileHit>>,
+}
+
+impl Events {
+    pub fn on_player_join<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut PlayerJoin) + Send + 'static,
+    {
+        self.player_join.push(Box::new(handler));
+    }
+
+    pub fn on_player_leave<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut PlayerLeave) + Send + 'static,
+    {
+        self.player_leave.push(Box::new(handler));
+    }
+
+    pub fn on_chat<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut Chat) + Send + 'static,
+    {
+        self.chat.push(Box::new(handler));
+    }
+
+    pub fn on_command<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut Command) + Send + 'static,
+    {
+        self.command.push(Box::new(handler));
+    }
+
+    pub fn on_spawn<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut Spawn) + Send + 'static,
+    {
+        self.spawn.push(Box::new(handler));
+    }
+
+    pub fn on_death<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut Death) + Send + 'static,
+    {
+        self.death.push(Box::new(handler));
+    }
+
+    pub fn on_brick_touched<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut BrickTouched) + Send + 'static,
+    {
+        self.brick_touched.push(Box::new(handler));
+    }
+
+    pub fn on_brick_touch_ended<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut BrickTouchEnded) + Send + 'static,
+    {
+        self.brick_touch_ended.push(Box::new(handler));
+    }
+
+    pub fn on_bot_touched<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut BotTouched) + Send + 'static,
+    {
+        self.bot_touched.push(Box::new(handler));
+    }
+
+    pub fn on_brick_clicked<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut BrickClicked) + Send + 'static,
+    {
+        self.brick_clicked.push(Box::new(handler));
+    }
+
+    pub fn on_player_input<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut PlayerInput) + Send + 'static,
+    {
+        self.player_input.push(Box::new(handler));
+    }
+
+    pub fn on_key_press<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut KeyPress) + Send + 'static,
+    {
+        self.key_press.push(Box::new(handler));
+    }
+
+    // Only runs for presses of `key`
+    pub fn on_key<F>(&mut self, key: &str, mut handler: F)
+    where
+        F: FnMut(&mut Game, &mut KeyPress) + Send + 'static,
+    {
+        let key = key.to_string();
+        self.on_key_press(move |game, event| {
+            if event.key == key {
+                handler(game, event);
+            }
+        });
+    }
+
+    pub fn on_key_release<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut KeyRelease) + Send + 'static,
+    {
+        self.key_release.push(Box::new(handler));
+    }
+
+    pub fn on_tool_activated<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut ToolActivated) + Send + 'static,
+    {
+        self.tool_activated.push(Box::new(handler));
+    }
+
+    pub fn on_tool_equipped<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut ToolEquipped) + Send + 'static,
+    {
+        self.tool_equipped.push(Box::new(handler));
+    }
+
+    pub fn on_projectile_hit<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Game, &mut ProjectileHit) + Send + 'static,
+    {
+        self.projectile_hit.push(Box::new(handler));
+    }
+}
+
+// Runs the handlers `select` picks out of `game.events`, stopping once the event is cancelled.
+// The handlers are taken out while they run so they can borrow the game mutably, anything
+// they register in the meantime is kept.
+pub fn emit<E: Cancellable>(
+    game: &mut Game,
+    select: fn(&mut Events) -> &mut Vec<Handler<E>>,
+    event: &mut E,
+) {
+    let mut handlers = std::mem::take(select(&mut game.events));
+
+    for handler in &mut handlers {
+        handler(game, event);
+        if event.cancelled() {
+            break;
+        }
+    }
+
+    handlers.append(select(&mut game.events));
+    *select(&mut game.events) = handlers;
+}
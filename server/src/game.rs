@@ -1,18 +1,94 @@
 use crate::{
-    brick,
+    avatar::Avatar,
+    bot::Bot,
+    brick::{Brick, Environment, Map, Vector3},
     buffer::{self, Buffer},
-    colour::{self, Color},
-    packet_builder,
+    collision::{self, Grid, Hit, Target},
+    colour::Color,
+    events::{self, Events, Input},
+    listing::Heartbeat,
+    message,
+    packet_builder::{self, Modification},
     player::{self, Player},
+    team::{self, Team},
+    timer::{self, Timer},
+    tool::{self, Tool},
 };
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{Instant, MissedTickBehavior};
+
+const REAPER_INTERVAL: Duration = Duration::from_secs(1);
+// Seconds admin prints stay up when the command doesn't say
+const PRINT_DURATION: u32 = 5;
+
+// Everything that touches the game goes through here, so only the game task ever owns it
+pub enum Event {
+    Join {
+        stream: OwnedWriteHalf,
+        disconnected: Arc<Notify>,
+        buffer: Buffer,
+        net_id: oneshot::Sender<u32>,
+    },
+    Packet {
+        net_id: u32,
+        packet_type: u8,
+        buffer: Buffer,
+    },
+    Leave {
+        net_id: u32,
+    },
+    Listing(oneshot::Sender<Heartbeat>),
+}
 
 pub struct Game {
     pub is_local: bool,
+    pub host_key: String,
+    pub port: u16,
+
+    pub map: String,
     pub brick_count: u32,
+    pub environment: Environment,
+    pub bricks: Vec<Brick>,
+    // Collision index over `bricks`, rebuild it with `index_bricks` after changing them
+    pub grid: Grid,
+    pub teams: Vec<Team>,
+    // Column names shown in the player list, set through `add_score_column`
+    pub leaderstats: Vec<String>,
+    // Given to every player when they join
+    pub default_tools: Vec<Tool>,
+
+    // Clients that haven't sent a heartbeat within this are treated as gone
+    pub heartbeat_timeout: Duration,
+    // Kick players that haven't moved or chatted within this, disabled when None
+    pub afk_timeout: Option<Duration>,
+    // How many packets can be queued for a player before they're disconnected as too slow
+    pub send_backlog: usize,
+    // How far "/local" chat carries
+    pub local_chat_radius: f32,
+    // Simulation ticks per second
+    pub tick_rate: u32,
+
+    // How far projectiles travel, and how far from the shooter they may start
+    pub projectile_range: f32,
+    pub projectile_reach: f32,
+
+    pub respawn_delay: Duration,
+    // Anyone below this height fell off the map and is killed
+    pub fall_height: f32,
+    next_spawn: usize,
+
+    pub players: Vec<Player>,
+    pub bots: Vec<Bot>,
 
-    pub players: Vec<Arc<Mutex<player::Player>>>,
+    pub events: Events,
+
+    pub timers: Vec<Timer>,
+    pub last_timer_id: u32,
+    // Timers cancelled while the timer list was taken out to run them
+    cancelled_timers: Vec<u32>,
 
     pub last_net_id: u32,
 }
@@ -20,142 +96,1641 @@ pub struct Game {
 pub fn new() -> Game {
     return Game {
         is_local: true,
+        host_key: String::new(),
+        port: 42480,
+        map: String::from("map.brk"),
         brick_count: 0,
+        environment: Environment::default(),
+        bricks: vec![],
+        grid: Grid::default(),
+        teams: vec![],
+        leaderstats: vec![],
+        default_tools: vec![],
+        heartbeat_timeout: Duration::from_secs(30),
+        afk_timeout: None,
+        send_backlog: 1024,
+        local_chat_radius: 50.0,
+        tick_rate: 20,
+        projectile_range: 500.0,
+        projectile_reach: 5.0,
+        respawn_delay: Duration::from_secs(5),
+        fall_height: -100.0,
+        next_spawn: 0,
         players: vec![],
+        bots: vec![],
+        events: Events::default(),
+        timers: vec![],
+        last_timer_id: 0,
+        cancelled_timers: vec![],
         last_net_id: 0,
     };
 }
 
+// Owns the game and applies events one at a time until every sender is gone
+pub async fn run(mut game: Game, mut events: mpsc::Receiver<Event>) {
+    // interval keeps ticks on schedule no matter how long each one takes, a slow tick just skips ahead
+    let mut tick = tokio::time::interval(Duration::from_secs(1) / game.tick_rate.max(1));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    game.every(REAPER_INTERVAL, |game| game.reap_players());
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => game.handle_event(event),
+                None => break,
+            },
+            _ = tick.tick() => game.tick(),
+        }
+    }
+}
+
+fn read_vector3(buffer: &mut Buffer) -> Option<Vector3> {
+    return Some(Vector3 {
+        x: buffer.read_float32()?,
+        y: buffer.read_float32()?,
+        z: buffer.read_float32()?,
+    });
+}
+
+// Input is `click, key` then optionally `pressed` and, for clicks, the ray's origin and direction.
+// Older clients only send the first two, which means a key press or a click without a ray.
+fn read_input(buffer: &mut Buffer, position: Vector3) -> Option<Input> {
+    let click = buffer.read_bool()?;
+    let key = buffer.read_string();
+    let pressed = buffer.read_bool().unwrap_or(true);
+
+    if click {
+        return match (read_vector3(buffer), read_vector3(buffer)) {
+            (Some(origin), Some(direction)) => Some(Input::Click {
+                position: origin,
+                direction: Some(direction),
+            }),
+            _ => Some(Input::Click {
+                position,
+                direction: None,
+            }),
+        };
+    }
+
+    if key.is_empty() {
+        return None;
+    }
+    if pressed {
+        return Some(Input::KeyDown(key));
+    }
+    return Some(Input::KeyUp(key));
+}
+
 impl Game {
-    pub async fn add_player(&mut self, player: Arc<Mutex<player::Player>>) {
-        self.players.push(player);
+    pub fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Join {
+                stream,
+                disconnected,
+                mut buffer,
+                net_id,
+            } => {
+                let mut player = player::new();
+                player.net_id = self.new_net_object();
+                player.disconnected = disconnected;
+                player.set_stream(stream, self.send_backlog);
+                player.check_auth(&mut buffer, self);
+
+                if net_id.send(player.net_id).is_err() {
+                    return;
+                }
+
+                let net_id = player.net_id;
+                self.add_player(player);
+
+                let mut event = events::PlayerJoin { net_id };
+                events::emit(self, |events| &mut events.player_join, &mut event);
+
+                let tools = self.default_tools.clone();
+                if let Some(player) = self.find_player(net_id) {
+                    for tool in tools {
+                        player.give_tool(tool);
+                    }
+                }
+
+                self.respawn(net_id);
+            }
+            Event::Packet {
+                net_id,
+                packet_type,
+                buffer,
+            } => self.handle_packet(net_id, packet_type, buffer),
+            Event::Leave { net_id } => self.remove_player(net_id),
+            Event::Listing(reply) => {
+                let _ = reply.send(self.build_heartbeat());
+            }
+        }
+    }
+
+    pub fn handle_packet(&mut self, net_id: u32, packet_type: u8, mut buffer: Buffer) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+
+        match packet_type {
+            2 => {
+                player.active();
+
+                let (Some(x), Some(y), Some(z), Some(rotation)) = (
+                    buffer.read_float32(),
+                    buffer.read_float32(),
+                    buffer.read_float32(),
+                    buffer.read_float32(),
+                ) else {
+                    return;
+                };
+
+                // NaN slips past every distance and height check, so those positions are dropped
+                let position = Vector3 { x, y, z };
+                if !position.is_finite() || !rotation.is_finite() {
+                    return;
+                }
+                player.set_position(position, rotation);
+            }
+            3 => {
+                player.active();
+
+                let command = buffer.read_string();
+                let args = buffer.read_string();
+                self.chatted(net_id, command, args);
+            }
+            4 => {
+                let (Some(tool), Some(origin), Some(direction)) = (
+                    buffer.read_uint32(),
+                    read_vector3(&mut buffer),
+                    read_vector3(&mut buffer),
+                ) else {
+                    return;
+                };
+                self.projectile(net_id, tool, origin, direction);
+            }
+            5 => {
+                let Some(brick) = buffer.read_uint32() else {
+                    return;
+                };
+                self.brick_clicked(net_id, brick);
+            }
+            6 => {
+                player.active();
+
+                let Some(input) = read_input(&mut buffer, player.position) else {
+                    return;
+                };
+                self.player_input(net_id, input);
+            }
+            7 => {
+                let (Some(equipping), Some(tool)) = (buffer.read_bool(), buffer.read_uint32())
+                else {
+                    return;
+                };
+
+                if equipping {
+                    self.tool_equipped(net_id, tool);
+                } else {
+                    player.equipped = None;
+                }
+            }
+            18 => player.heartbeat(),
+            _ => {}
+        }
+    }
+
+    pub fn find_brick(&self, net_id: u32) -> Option<&Brick> {
+        return self.bricks.iter().find(|brick| brick.net_id == net_id);
+    }
+
+    // Clicks only count on clickable bricks within reach of where the server thinks the player is
+    fn brick_clicked(&mut self, net_id: u32, brick: u32) {
+        let Some(player) = self.players.iter().find(|plr| plr.net_id == net_id) else {
+            return;
+        };
+        if !player.alive {
+            return;
+        }
+        let Some(clicked) = self.find_brick(brick) else {
+            return;
+        };
+        if !clicked.clickable {
+            return;
+        }
+
+        // Measured to the closest point on the brick so big bricks can be clicked from their edge
+        let bounds = collision::brick_bounds(clicked);
+        if bounds.distance_to(player.position) > clicked.click_distance {
+            return;
+        }
+
+        let mut event = events::BrickClicked {
+            net_id,
+            brick,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.brick_clicked, &mut event);
+    }
+
+    fn player_input(&mut self, net_id: u32, input: Input) {
+        let mut event = events::PlayerInput {
+            net_id,
+            input,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.player_input, &mut event);
+        if event.cancelled {
+            return;
+        }
+
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+
+        match event.input {
+            Input::KeyDown(key) => {
+                let mut event = events::KeyPress {
+                    net_id,
+                    key: player.bound_key(key),
+                    cancelled: false,
+                };
+                events::emit(self, |events| &mut events.key_press, &mut event);
+            }
+            Input::KeyUp(key) => {
+                let mut event = events::KeyRelease {
+                    net_id,
+                    key: player.bound_key(key),
+                };
+                events::emit(self, |events| &mut events.key_release, &mut event);
+            }
+            Input::Click { .. } => {
+                if let Some(tool) = player.equipped {
+                    self.tool_activated(net_id, tool);
+                }
+            }
+        }
+    }
+
+    // First brick or living player along the ray. Anything the ray starts inside is ignored,
+    // so a ray fired from inside a player never hits them.
+    pub fn raycast(&self, origin: Vector3, direction: Vector3, max_distance: f32) -> Option<Hit> {
+        let length =
+            (direction.x * direction.x + direction.y * direction.y + direction.z * direction.z)
+                .sqrt();
+        if length == 0.0 || !length.is_finite() || !origin.is_finite() {
+            return None;
+        }
+        let max_distance = max_distance.min(collision::MAX_RAY_DISTANCE);
+        let direction = Vector3 {
+            x: direction.x / length,
+            y: direction.y / length,
+            z: direction.z / length,
+        };
+        let inverse = Vector3 {
+            x: 1.0 / direction.x,
+            y: 1.0 / direction.y,
+            z: 1.0 / direction.z,
+        };
+
+        let mut closest =
+            self.grid
+                .raycast(origin, direction, max_distance)
+                .and_then(|(index, distance)| {
+                    let brick = self.bricks.get(index)?;
+                    Some((Target::Brick(brick.net_id), distance))
+                });
+
+        for plr in self.players.iter().filter(|plr| plr.alive) {
+            let bounds = collision::player_bounds(plr.position);
+            let Some(distance) = bounds.ray_distance(origin, inverse) else {
+                continue;
+            };
+            if distance <= max_distance && closest.is_none_or(|(_, best)| distance < best) {
+                closest = Some((Target::Player(plr.net_id), distance));
+            }
+        }
+
+        let (target, distance) = closest?;
+        return Some(Hit {
+            target,
+            position: Vector3 {
+                x: origin.x + direction.x * distance,
+                y: origin.y + direction.y * distance,
+                z: origin.z + direction.z * distance,
+            },
+            distance,
+        });
+    }
+
+    // Projectiles are only trusted from a living player holding the tool they came from,
+    // fired from near where the server has them, and hit whatever the server's own raycast says
+    fn projectile(&mut self, net_id: u32, tool: u32, origin: Vector3, direction: Vector3) {
+        let Some(player) = self.players.iter().find(|plr| plr.net_id == net_id) else {
+            return;
+        };
+        if !player.alive || player.equipped != Some(tool) {
+            return;
+        }
+        match player.find_tool(tool) {
+            Some(tool) if tool.enabled => {}
+            _ => return,
+        }
+
+        // NaN would pass the reach check below
+        if !origin.is_finite() || !direction.is_finite() {
+            return;
+        }
+        let bounds = collision::player_bounds(player.position);
+        if bounds.distance_to(origin) > self.projectile_reach {
+            return;
+        }
+
+        let Some(hit) = self.raycast(origin, direction, self.projectile_range) else {
+            return;
+        };
+
+        let mut event = events::ProjectileHit {
+            net_id,
+            tool,
+            hit,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.projectile_hit, &mut event);
+    }
+
+    // Creates a tool with its own net id, ready to give to players
+    pub fn new_tool(&mut self, name: String) -> Tool {
+        let mut tool = tool::new(name);
+        tool.net_id = self.new_net_object();
+
+        return tool;
+    }
+
+    fn tool_activated(&mut self, net_id: u32, tool: u32) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        match player.find_tool(tool) {
+            Some(tool) if tool.enabled => {}
+            _ => return,
+        }
+
+        let mut event = events::ToolActivated {
+            net_id,
+            tool,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.tool_activated, &mut event);
+    }
+
+    fn tool_equipped(&mut self, net_id: u32, tool: u32) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        let allowed = matches!(player.find_tool(tool), Some(tool) if tool.enabled);
+
+        let mut event = events::ToolEquipped {
+            net_id,
+            tool,
+            cancelled: !allowed,
+        };
+        if allowed {
+            events::emit(self, |events| &mut events.tool_equipped, &mut event);
+        }
+
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+
+        if event.cancelled {
+            // The client already has it in hand, take it back out
+            player.equipped = None;
+            player.modify(Modification::EquipTool(0));
+            return;
+        }
+        player.equipped = Some(tool);
+    }
+
+    pub fn tick(&mut self) {
+        self.run_timers();
+        self.update_bots();
+        self.check_deaths();
+        self.update_touches();
+        self.send_figures();
+        self.send_team_changes();
+        self.send_name_changes();
+        self.send_score_changes();
+    }
+
+    pub fn load_map(&mut self, map: Map) {
+        self.environment = map.environment;
+        self.brick_count = map.bricks.len() as u32;
+        self.bricks = map.bricks;
+        self.index_bricks();
+
+        for team in map.teams {
+            self.add_team(team.name, team.colour);
+        }
+
+        for tool in map.tools {
+            let mut default_tool = self.new_tool(tool.name);
+            default_tool.model = tool.model;
+            self.default_tools.push(default_tool);
+        }
+    }
+
+    pub fn index_bricks(&mut self) {
+        self.grid = collision::new(&self.bricks);
+    }
+
+    // Net ids of the collidable bricks overlapping `bounds`
+    pub fn bricks_in(&self, bounds: &collision::Aabb) -> Vec<u32> {
+        return self
+            .grid
+            .query(bounds)
+            .into_iter()
+            .filter_map(|index| self.bricks.get(index))
+            .map(|brick| brick.net_id)
+            .collect();
+    }
+
+    // Fires touches for bricks players walked into this tick, and touch endings for ones they left
+    fn update_touches(&mut self) {
+        let mut started = vec![];
+        let mut ended = vec![];
+
+        for index in 0..self.players.len() {
+            let plr = &self.players[index];
+            let touching = if plr.alive {
+                self.bricks_in(&collision::player_bounds(plr.position))
+            } else {
+                vec![]
+            };
+
+            let plr = &mut self.players[index];
+            for brick in &touching {
+                if !plr.touching.contains(brick) {
+                    started.push((plr.net_id, *brick));
+                }
+            }
+            for brick in &plr.touching {
+                if !touching.contains(brick) {
+                    ended.push((plr.net_id, *brick));
+                }
+            }
+            plr.touching = touching;
+        }
+
+        for (net_id, brick) in ended {
+            let mut event = events::BrickTouchEnded { net_id, brick };
+            events::emit(self, |events| &mut events.brick_touch_ended, &mut event);
+        }
+        for (net_id, brick) in started {
+            let mut event = events::BrickTouched {
+                net_id,
+                brick,
+                cancelled: false,
+            };
+            events::emit(self, |events| &mut events.brick_touched, &mut event);
+        }
+    }
+
+    // Creates a team and shows it to everyone, returns its net id
+    pub fn add_team(&mut self, name: String, colour: Color) -> u32 {
+        let mut team = team::new(name, colour);
+        team.net_id = self.new_net_object();
+
+        let packet = packet_builder::build_team_packet(&team);
+        self.broadcast_packet(&packet);
+
+        let net_id = team.net_id;
+        self.teams.push(team);
+
+        return net_id;
+    }
+
+    pub fn find_team(&self, net_id: u32) -> Option<&Team> {
+        return self.teams.iter().find(|team| team.net_id == net_id);
+    }
+
+    pub fn team_size(&self, net_id: u32) -> usize {
+        return self
+            .players
+            .iter()
+            .filter(|plr| plr.team == Some(net_id))
+            .count();
+    }
+
+    // Puts everyone without a team on the smallest one, then evens the teams out
+    pub fn autobalance(&mut self) {
+        if self.teams.is_empty() {
+            return;
+        }
+
+        loop {
+            let sizes: Vec<(u32, usize)> = self
+                .teams
+                .iter()
+                .map(|team| (team.net_id, self.team_size(team.net_id)))
+                .collect();
+            let (smallest, smallest_size) = *sizes.iter().min_by_key(|(_, size)| *size).unwrap();
+            let (largest, largest_size) = *sizes.iter().max_by_key(|(_, size)| *size).unwrap();
+
+            let moving = match self.players.iter().position(|plr| plr.team.is_none()) {
+                Some(index) => index,
+                None if largest_size > smallest_size + 1 => self
+                    .players
+                    .iter()
+                    .rposition(|plr| plr.team == Some(largest))
+                    .unwrap(),
+                None => break,
+            };
+
+            self.players[moving].set_team(Some(smallest));
+        }
+    }
+
+    fn send_team_changes(&mut self) {
+        let mut packets = vec![];
+        for plr in &mut self.players {
+            if !plr.team_changed {
+                continue;
+            }
+            plr.team_changed = false;
+
+            let team = plr.team.unwrap_or(0);
+            packets.push(packet_builder::build_team_figure_packet(plr.net_id, team));
+        }
+
+        for packet in packets {
+            self.broadcast_packet(&packet);
+        }
+    }
+
+    pub fn top_print_all(&mut self, message: String, seconds: u32) {
+        let packet =
+            packet_builder::build_modification_packet(Modification::TopPrint(message, seconds));
+        self.broadcast_packet(&packet);
+    }
+
+    pub fn center_print_all(&mut self, message: String, seconds: u32) {
+        let packet =
+            packet_builder::build_modification_packet(Modification::CenterPrint(message, seconds));
+        self.broadcast_packet(&packet);
+    }
+
+    pub fn bottom_print_all(&mut self, message: String, seconds: u32) {
+        let packet =
+            packet_builder::build_modification_packet(Modification::BottomPrint(message, seconds));
+        self.broadcast_packet(&packet);
+    }
+
+    // "/centerprint 10 Round over" shows the message to everyone for 10 seconds,
+    // the duration can be left out
+    fn admin_print(&mut self, net_id: u32, command: &str, args: String) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        if !player.admin {
+            player.message(String::from(
+                "\\c6You don't have permission to use that command.",
+            ));
+            return;
+        }
+
+        let (seconds, message) = match args.split_once(' ') {
+            Some((seconds, message)) => match seconds.parse::<u32>() {
+                Ok(seconds) => (seconds, message.to_string()),
+                Err(_) => (PRINT_DURATION, args),
+            },
+            None => (PRINT_DURATION, args),
+        };
 
-        let players_length = self.players.len();
+        match command {
+            "topprint" => self.top_print_all(message, seconds),
+            "centerprint" => self.center_print_all(message, seconds),
+            _ => self.bottom_print_all(message, seconds),
+        }
+    }
 
-        let last_player = &self.players[players_length - 1].clone();
-        let last_player_lock = last_player.lock().await;
+    pub fn add_score_column(&mut self, column: String) {
+        if self.leaderstats.contains(&column) {
+            return;
+        }
+        self.leaderstats.push(column);
+
+        let packet = packet_builder::build_modification_packet(Modification::Leaderstats(
+            self.leaderstats.clone(),
+        ));
+        self.broadcast_packet(&packet);
+    }
+
+    // Only values in a scoreboard column are sent, the rest stay on the player
+    fn send_score_changes(&mut self) {
+        let mut packets = vec![];
+        for plr in &mut self.players {
+            for column in std::mem::take(&mut plr.scores_changed) {
+                if !self.leaderstats.contains(&column) {
+                    continue;
+                }
+
+                let value = plr.score(&column);
+                packets.push(packet_builder::build_modification_packet(
+                    Modification::Score(plr.net_id, column, value),
+                ));
+            }
+        }
+
+        for packet in packets {
+            self.broadcast_packet(&packet);
+        }
+    }
+
+    fn send_name_changes(&mut self) {
+        let mut packets = vec![];
+        for plr in &mut self.players {
+            if !plr.name_changed {
+                continue;
+            }
+            plr.name_changed = false;
+
+            packets.push(packet_builder::build_name_figure_packet(
+                plr.net_id,
+                plr.shown_name(),
+                plr.name_tag,
+                plr.speech.clone(),
+            ));
+        }
+
+        for packet in packets {
+            self.broadcast_packet(&packet);
+        }
+    }
+
+    // Sends a chat message only to the sender's team
+    pub fn team_chat(&mut self, net_id: u32, message: String) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        let Some(team) = player.team else {
+            return;
+        };
+        let username = player.username.clone();
+
+        let colour = match self.find_team(team) {
+            Some(team) => team.colour,
+            None => return,
+        };
+
+        let packet = packet_builder::build_message_packet(format!(
+            "\\c6[TEAM] {}{}: \\c0{}",
+            message::colour_code(&colour),
+            username,
+            message::escape(&message)
+        ));
+        self.send_chat(net_id, &packet, |plr| plr.team == Some(team));
+    }
+
+    // Only admins send to or hear the admin channel
+    pub fn admin_chat(&mut self, net_id: u32, message: String) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        if !player.admin {
+            self.tell(net_id, "You don't have permission to use that command.");
+            return;
+        }
+
+        let packet = packet_builder::build_message_packet(format!(
+            "\\c6[ADMIN] {}: \\c0{}",
+            self.chat_name(net_id),
+            message::escape(&message)
+        ));
+        self.send_chat(net_id, &packet, |plr| plr.admin);
+    }
+
+    // Heard by everyone within `local_chat_radius` of the sender
+    pub fn local_chat(&mut self, net_id: u32, message: String) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        let position = player.position;
+        let radius = self.local_chat_radius;
+
+        let packet = packet_builder::build_message_packet(format!(
+            "\\c6[LOCAL] {}: \\c0{}",
+            self.chat_name(net_id),
+            message::escape(&message)
+        ));
+        self.send_chat(net_id, &packet, |plr| {
+            let x = plr.position.x - position.x;
+            let y = plr.position.y - position.y;
+            let z = plr.position.z - position.z;
+            (x * x + y * y + z * z).sqrt() <= radius
+        });
+    }
+
+    // "/pm name message", the two of them can then "/reply" to each other
+    pub fn private_message(&mut self, net_id: u32, args: String) {
+        // Names can have spaces in them, so go with the longest one the message starts with
+        let recipient = self
+            .players
+            .iter()
+            .filter(|plr| {
+                let name = plr.username.as_bytes();
+                args.len() > name.len()
+                    && args.as_bytes()[..name.len()].eq_ignore_ascii_case(name)
+                    && args.as_bytes()[name.len()] == b' '
+            })
+            .max_by_key(|plr| plr.username.len())
+            .map(|plr| {
+                (
+                    plr.net_id,
+                    args[plr.username.len() + 1..].trim().to_string(),
+                )
+            });
+
+        match recipient {
+            Some((recipient, message)) if !message.is_empty() => {
+                self.send_private_message(net_id, recipient, message);
+            }
+            _ => self.tell(net_id, "Usage: /pm <player> <message>"),
+        }
+    }
+
+    pub fn reply(&mut self, net_id: u32, message: String) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        let reply_to = player.reply_to;
+
+        match reply_to {
+            Some(recipient) if self.players.iter().any(|plr| plr.net_id == recipient) => {
+                self.send_private_message(net_id, recipient, message);
+            }
+            _ => self.tell(net_id, "There's nobody to reply to."),
+        }
+    }
+
+    fn send_private_message(&mut self, net_id: u32, recipient: u32, message: String) {
+        if net_id == recipient {
+            return;
+        }
 
+        let from = self.chat_name(net_id);
+        let to = self.chat_name(recipient);
+        let message = message::escape(&message);
+
+        if let Some(player) = self.find_player(net_id) {
+            player.reply_to = Some(recipient);
+            player.message(format!("\\c6[PM to {}\\c6]: \\c0{}", to, message));
+        }
+
+        let Some(player) = self.find_player(recipient) else {
+            return;
+        };
+        if player.is_ignoring(net_id) {
+            return;
+        }
+        player.reply_to = Some(net_id);
+        player.message(format!("\\c6[PM from {}\\c6]: \\c0{}", from, message));
+    }
+
+    // "/ignore name" stops their chat reaching you until "/unignore name"
+    fn set_ignoring(&mut self, net_id: u32, name: String, ignoring: bool) {
+        let Some(other) = self.find_player_by_name(&name) else {
+            self.tell(net_id, "There's no player with that name.");
+            return;
+        };
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+
+        if ignoring {
+            player.ignore(other);
+        } else {
+            player.unignore(other);
+        }
+    }
+
+    // Sends a chat packet to everyone `recipient` picks, apart from anyone ignoring the sender
+    pub fn send_chat<F>(&mut self, net_id: u32, packet: &Buffer, recipient: F)
+    where
+        F: Fn(&Player) -> bool,
+    {
+        for plr in &mut self.players {
+            if plr.net_id != net_id && (!recipient(plr) || plr.is_ignoring(net_id)) {
+                continue;
+            }
+
+            plr.send_packet(packet.clone());
+        }
+    }
+
+    // Sends a line to everyone's chat box
+    pub fn message_all(&mut self, text: String) {
+        let packet = packet_builder::build_message_packet(text);
+        self.broadcast_packet(&packet);
+    }
+
+    // A server notice for one player
+    fn tell(&mut self, net_id: u32, message: &str) {
+        if let Some(player) = self.find_player(net_id) {
+            player.message(format!("\\c6{}", message));
+        }
+    }
+
+    pub fn find_player_by_name(&self, name: &str) -> Option<u32> {
+        return self
+            .players
+            .iter()
+            .find(|plr| plr.username.eq_ignore_ascii_case(name))
+            .map(|plr| plr.net_id);
+    }
+
+    // Players on a team get their name in the team's colour
+    fn chat_name(&self, net_id: u32) -> String {
+        let Some(player) = self.players.iter().find(|plr| plr.net_id == net_id) else {
+            return String::new();
+        };
+
+        return match player.team.and_then(|team| self.find_team(team)) {
+            Some(team) => format!("{}{}", message::colour_code(&team.colour), player.username),
+            None => format!("\\c6 {}", player.username),
+        };
+    }
+
+    fn check_deaths(&mut self) {
+        let dying: Vec<u32> = self
+            .players
+            .iter()
+            .filter(|plr| plr.alive && (plr.health <= 0.0 || plr.position.z < self.fall_height))
+            .map(|plr| plr.net_id)
+            .collect();
+
+        for net_id in dying {
+            self.kill(net_id);
+        }
+    }
+
+    pub fn kill(&mut self, net_id: u32) {
+        match self.find_player(net_id) {
+            Some(player) if player.alive => {}
+            _ => return,
+        }
+
+        let mut event = events::Death {
+            net_id,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.death, &mut event);
+
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+
+        if event.cancelled {
+            // Saved from death, start them over at full health
+            player.health = player.max_health;
+            return;
+        }
+
+        player.health = 0.0;
+        player.alive = false;
+
+        let packet = packet_builder::build_kill_packet(net_id, true);
+        self.broadcast_packet(&packet);
+
+        let delay = self.respawn_delay;
+        self.after(delay, move |game| game.respawn(net_id));
+    }
+
+    // Top centre of every brick named "spawn", or the origin when the map has none
+    pub fn spawn_points(&self) -> Vec<Vector3> {
+        let mut points: Vec<Vector3> = self
+            .bricks
+            .iter()
+            .filter(|brick| brick.name.eq_ignore_ascii_case("spawn"))
+            .map(|brick| brick.top_centre())
+            .collect();
+
+        if points.is_empty() {
+            points.push(Vector3::default());
+        }
+
+        return points;
+    }
+
+    pub fn respawn(&mut self, net_id: u32) {
+        if self.find_player(net_id).is_none() {
+            return;
+        }
+
+        // Spawn points are handed out in turn so players don't stack up on one
+        let points = self.spawn_points();
+        let position = points[self.next_spawn % points.len()];
+        self.next_spawn += 1;
+
+        let mut event = events::Spawn {
+            net_id,
+            position,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.spawn, &mut event);
+        if event.cancelled {
+            return;
+        }
+
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
+        player.health = player.max_health;
+        player.alive = true;
+        player.position = event.position;
+        player.moved = false;
+
+        let rotation = player.rotation;
+
+        self.broadcast_packet(&packet_builder::build_kill_packet(net_id, false));
+        self.broadcast_packet(&packet_builder::build_figure_packet(
+            net_id,
+            event.position,
+            rotation,
+        ));
+    }
+
+    // Runs `callback` every `period` from the game loop, returns an id for `cancel`
+    pub fn every<F>(&mut self, period: Duration, callback: F) -> u32
+    where
+        F: FnMut(&mut Game) + Send + 'static,
+    {
+        self.last_timer_id += 1;
+        let timer = timer::new(self.last_timer_id, period, Some(period), Box::new(callback));
+        self.timers.push(timer);
+
+        return self.last_timer_id;
+    }
+
+    // Runs `callback` once after `delay`, returns an id for `cancel`
+    pub fn after<F>(&mut self, delay: Duration, callback: F) -> u32
+    where
+        F: FnOnce(&mut Game) + Send + 'static,
+    {
+        let mut callback = Some(callback);
+
+        self.last_timer_id += 1;
+        let timer = timer::new(
+            self.last_timer_id,
+            delay,
+            None,
+            Box::new(move |game| {
+                if let Some(callback) = callback.take() {
+                    callback(game);
+                }
+            }),
+        );
+        self.timers.push(timer);
+
+        return self.last_timer_id;
+    }
+
+    pub fn cancel(&mut self, id: u32) {
+        self.timers.retain(|timer| timer.id != id);
+        self.cancelled_timers.push(id);
+    }
+
+    fn run_timers(&mut self) {
+        let now = Instant::now();
+        let mut timers = std::mem::take(&mut self.timers);
+
+        timers.retain_mut(|timer| {
+            if self.cancelled_timers.contains(&timer.id) {
+                return false;
+            }
+            if timer.due > now {
+                return true;
+            }
+
+            return timer.fire(self, now) && !self.cancelled_timers.contains(&timer.id);
+        });
+
+        // Keep anything scheduled by the callbacks themselves
+        timers.append(&mut self.timers);
+        self.timers = timers;
+        self.cancelled_timers.clear();
+    }
+
+    // Sends every figure that changed this tick, batched into one write per player
+    fn send_figures(&mut self) {
+        let mut figures = vec![];
+        for plr in &mut self.players {
+            if !plr.moved {
+                continue;
+            }
+            plr.moved = false;
+
+            let packet =
+                packet_builder::build_figure_packet(plr.net_id, plr.position, plr.rotation);
+            figures.push((plr.net_id, packet));
+        }
+        for bot in &mut self.bots {
+            if !bot.moved {
+                continue;
+            }
+            bot.moved = false;
+
+            let packet =
+                packet_builder::build_figure_packet(bot.net_id, bot.position, bot.rotation);
+            figures.push((bot.net_id, packet));
+        }
+
+        if figures.is_empty() {
+            return;
+        }
+
+        for plr in &mut self.players {
+            let mut batch = buffer::new(None);
+            for (net_id, packet) in &figures {
+                if *net_id != plr.net_id {
+                    batch.data.extend_from_slice(&packet.data);
+                }
+            }
+
+            if !batch.data.is_empty() {
+                plr.send_packet(batch);
+            }
+        }
+    }
+
+    // Puts a bot in the world and shows it to everyone, returns its net id
+    pub fn add_bot(&mut self, mut bot: Bot) -> u32 {
+        bot.net_id = self.new_net_object();
+        bot.moved = false;
+
+        self.broadcast_packet(&packet_builder::build_bot_packet(&bot));
+        self.broadcast_packet(&packet_builder::build_avatar_packet(
+            bot.net_id,
+            &bot.avatar,
+        ));
+
+        let net_id = bot.net_id;
+        self.bots.push(bot);
+
+        return net_id;
+    }
+
+    pub fn find_bot(&mut self, net_id: u32) -> Option<&mut Bot> {
+        return self.bots.iter_mut().find(|bot| bot.net_id == net_id);
+    }
+
+    pub fn remove_bot(&mut self, net_id: u32) {
+        let Some(index) = self.bots.iter().position(|bot| bot.net_id == net_id) else {
+            return;
+        };
+        self.bots.remove(index);
+
+        self.broadcast_packet(&packet_builder::build_destroy_bot_packet(net_id));
+    }
+
+    // Runs every bot's behaviour, then fires touches for players that just walked into one
+    fn update_bots(&mut self) {
+        if self.bots.is_empty() {
+            return;
+        }
+
+        let seconds = 1.0 / self.tick_rate.max(1) as f32;
+        let players: Vec<(u32, Vector3)> = self
+            .players
+            .iter()
+            .filter(|plr| plr.alive)
+            .map(|plr| (plr.net_id, plr.position))
+            .collect();
+        let positions: Vec<Vector3> = players.iter().map(|(_, position)| *position).collect();
+
+        let mut touches = vec![];
+        for bot in &mut self.bots {
+            bot.update(seconds, &positions);
+
+            let touching: Vec<u32> = players
+                .iter()
+                .filter(|(_, position)| bot.touches(*position))
+                .map(|(net_id, _)| *net_id)
+                .collect();
+            for net_id in &touching {
+                if !bot.touching.contains(net_id) {
+                    touches.push((bot.net_id, *net_id));
+                }
+            }
+            bot.touching = touching;
+        }
+
+        for (bot, net_id) in touches {
+            let mut event = events::BotTouched {
+                net_id,
+                bot,
+                cancelled: false,
+            };
+            events::emit(self, |events| &mut events.bot_touched, &mut event);
+        }
+    }
+
+    pub fn add_player(&mut self, player: Player) {
         let mut packet: Buffer = buffer::new(None);
         packet.write_byte(3);
         packet.write_byte(1);
-        packet.write_uint32(last_player_lock.net_id);
-        packet.write_string(last_player_lock.username.clone());
-        packet.write_uint32(last_player_lock.user_id);
+        packet.write_uint32(player.net_id);
+        packet.write_string(player.username.clone());
+        packet.write_uint32(player.user_id);
 
-        packet.write_byte(last_player_lock.admin as u8);
-        packet.write_byte(last_player_lock.membership);
+        packet.write_byte(player.admin as u8);
+        packet.write_byte(player.membership);
         packet.write_uint_v();
 
-        let net_id = last_player_lock.net_id;
-        drop(last_player_lock);
-
-        self.broadcast_packet_except(packet, net_id).await;
+        self.broadcast_packet(&packet);
 
         let mut packet: Buffer = buffer::new(None);
         packet.write_byte(3);
-        packet.write_byte(players_length as u8 - 1);
+        packet.write_byte(self.players.len() as u8);
 
-        let mut count = 0;
         for plr in &self.players {
-            let unlocked = plr.lock().await;
-            if unlocked.net_id == net_id {
-                drop(unlocked);
-                continue;
-            }
-            count += 1;
+            packet.write_uint32(plr.net_id);
+            packet.write_string(plr.username.clone());
+            packet.write_uint32(plr.user_id);
 
-            packet.write_uint32(unlocked.net_id);
-            packet.write_string(unlocked.username.clone());
-            packet.write_uint32(unlocked.user_id);
+            packet.write_byte(plr.admin as u8);
+            packet.write_byte(plr.membership);
+        }
+
+        let avatar = packet_builder::build_avatar_packet(player.net_id, &player.avatar);
+        let avatars: Vec<Buffer> = self
+            .players
+            .iter()
+            .map(|plr| packet_builder::build_avatar_packet(plr.net_id, &plr.avatar))
+            .collect();
+
+        self.players.push(player);
+        self.broadcast_packet(&avatar);
 
-            packet.write_byte(unlocked.admin as u8);
-            packet.write_byte(unlocked.membership);
+        let bricks = packet_builder::build_bricks_packet(&self.bricks);
+        let teams: Vec<Buffer> = self
+            .teams
+            .iter()
+            .map(packet_builder::build_team_packet)
+            .collect();
+        let memberships: Vec<Buffer> = self
+            .players
+            .iter()
+            .filter_map(|plr| {
+                let team = plr.team?;
+                Some(packet_builder::build_team_figure_packet(plr.net_id, team))
+            })
+            .collect();
+
+        let names: Vec<Buffer> = self
+            .players
+            .iter()
+            .filter(|plr| plr.display_name.is_some() || !plr.name_tag || !plr.speech.is_empty())
+            .map(|plr| {
+                packet_builder::build_name_figure_packet(
+                    plr.net_id,
+                    plr.shown_name(),
+                    plr.name_tag,
+                    plr.speech.clone(),
+                )
+            })
+            .collect();
+
+        let mut scores = vec![];
+        if !self.leaderstats.is_empty() {
+            scores.push(packet_builder::build_modification_packet(
+                Modification::Leaderstats(self.leaderstats.clone()),
+            ));
+        }
+        for plr in &self.players {
+            for column in &self.leaderstats {
+                if !plr.scores.contains_key(column) {
+                    continue;
+                }
+                scores.push(packet_builder::build_modification_packet(
+                    Modification::Score(plr.net_id, column.clone(), plr.score(column)),
+                ));
+            }
         }
 
-        if count > 0 {
-            let last_player = &self.players[self.players.len() - 1].clone();
-            let mut last_player_lock = last_player.lock().await;
+        let mut bots = vec![];
+        for bot in &self.bots {
+            bots.push(packet_builder::build_bot_packet(bot));
+            bots.push(packet_builder::build_avatar_packet(bot.net_id, &bot.avatar));
+        }
 
+        let new_player = self.players.last_mut().unwrap();
+        new_player.send_packet(bricks);
+        if !avatars.is_empty() {
             packet.write_uint_v();
-            last_player_lock.send_packet(packet).await;
+            new_player.send_packet(packet);
+        }
+        for avatar in avatars {
+            new_player.send_packet(avatar);
+        }
+        for team in teams {
+            new_player.send_packet(team);
+        }
+        for membership in memberships {
+            new_player.send_packet(membership);
+        }
+        for name in names {
+            new_player.send_packet(name);
+        }
+        for score in scores {
+            new_player.send_packet(score);
+        }
+        for bot in bots {
+            new_player.send_packet(bot);
         }
     }
 
-    pub async fn send_brick(&mut self, net_id: u32, brick: brick::Brick) {
-        let mut packet: Buffer = buffer::new(None);
-        packet.write_byte(17); // SendBrick type
-        packet.write_uint32(1); // Amount of bricks
+    // Replaces a player's appearance and shows it to everyone, including themselves
+    pub fn set_avatar(&mut self, net_id: u32, avatar: Avatar) {
+        let Some(player) = self.find_player(net_id) else {
+            return;
+        };
 
-        packet.write_uint32(brick.net_id);
-        packet.write_float32(brick.position.x);
-        packet.write_float32(brick.position.y);
-        packet.write_float32(brick.position.z);
+        let packet = packet_builder::build_avatar_packet(net_id, &avatar);
+        player.avatar = avatar;
 
-        packet.write_float32(brick.scale.x);
-        packet.write_float32(brick.scale.y);
-        packet.write_float32(brick.scale.z);
+        self.broadcast_packet(&packet);
+    }
 
-        let rgb = colour::convert_hexcode_to_rgb(brick.colour).unwrap();
-        packet.write_uint32((rgb.red | (rgb.green << 8) | (rgb.blue << 16)) as u32);
+    pub fn send_brick(&mut self, net_id: u32, brick: Brick) {
+        let packet = packet_builder::build_bricks_packet(std::slice::from_ref(&brick));
 
-        packet.write_float32(brick.visibility);
+        if let Some(player) = self.find_player(net_id) {
+            player.send_packet(packet);
+        }
+    }
 
-        let player = self.find_player(net_id).await;
-        let mut unlocked = player.lock().await;
-        unlocked.send_packet(packet).await;
+    pub fn find_player(&mut self, net_id: u32) -> Option<&mut Player> {
+        return self.players.iter_mut().find(|plr| plr.net_id == net_id);
     }
 
-    pub async fn find_player(&mut self, net_id: u32) -> &Arc<Mutex<Player>> {
+    pub fn remove_player(&mut self, net_id: u32) {
+        if self.find_player(net_id).is_none() {
+            return;
+        }
+
+        // Handlers still get to see the player before they're gone
+        let mut event = events::PlayerLeave { net_id };
+        events::emit(self, |events| &mut events.player_leave, &mut event);
+
+        let Some(index) = self.players.iter().position(|plr| plr.net_id == net_id) else {
+            return;
+        };
+
+        let mut player = self.players.remove(index);
+        player.disconnect();
+
+        let packet = packet_builder::build_remove_player_packet(net_id);
+        self.broadcast_packet(&packet);
+    }
+
+    pub fn reap_players(&mut self) {
+        let now = Instant::now();
+
+        let mut timed_out = vec![];
+        let mut idle = vec![];
+
         for plr in &self.players {
-            let unlocked = plr.lock().await;
-            if unlocked.net_id == net_id {
-                return &plr;
+            if now.duration_since(plr.last_heartbeat) > self.heartbeat_timeout {
+                timed_out.push(plr.net_id);
+                continue;
+            }
+
+            if let Some(afk_timeout) = self.afk_timeout {
+                if now.duration_since(plr.last_activity) > afk_timeout {
+                    idle.push(plr.net_id);
+                }
             }
         }
-        return &self.players[0];
+
+        for net_id in idle {
+            if let Some(player) = self.find_player(net_id) {
+                println!("Kicking {} for being AFK", player.username);
+                player.kick(String::from("You have been kicked for being AFK."));
+            }
+
+            self.remove_player(net_id);
+        }
+
+        for net_id in timed_out {
+            println!("Player {} timed out", net_id);
+            self.remove_player(net_id);
+        }
+    }
+
+    pub fn build_heartbeat(&self) -> Heartbeat {
+        return Heartbeat {
+            host_key: self.host_key.clone(),
+            port: self.port,
+            players: self.players.len() as u32,
+            map: self.map.clone(),
+            brick_count: self.brick_count,
+        };
     }
 
+    // Net ids start at 1 so 0 is free to mean "none" on the wire
     pub fn new_net_object(&mut self) -> u32 {
         self.last_net_id += 1;
-        return self.last_net_id - 1;
+        return self.last_net_id;
     }
 
-    pub async fn broadcast_packet(&mut self, buf: &Buffer) {
-        for plr in &self.players {
-            let mut unlocked = plr.lock().await;
-            let _ = unlocked.send_packet(buf.clone()).await;
+    pub fn broadcast_packet(&mut self, buf: &Buffer) {
+        for plr in &mut self.players {
+            plr.send_packet(buf.clone());
         }
     }
 
-    pub async fn broadcast_packet_except(&mut self, buf: Buffer, net_id: u32) {
-        for plr in &self.players {
-            let mut unlocked = plr.lock().await;
-            if unlocked.net_id == net_id {
+    pub fn broadcast_packet_except(&mut self, buf: Buffer, net_id: u32) {
+        for plr in &mut self.players {
+            if plr.net_id == net_id {
                 continue;
             }
 
-            let _ = unlocked.send_packet(buf.clone()).await;
+            plr.send_packet(buf.clone());
         }
     }
 
-    pub async fn chatted(&mut self, net_id: u32, command: String, args: String) {
-        let player = self.find_player(net_id).await;
-        let player_clone = Arc::clone(player);
-
-        let username = player_clone.lock().await.username.clone();
-        drop(player_clone);
+    pub fn chatted(&mut self, net_id: u32, command: String, args: String) {
+        if self.find_player(net_id).is_none() {
+            return;
+        }
 
         if command != "chat" {
+            let mut event = events::Command {
+                net_id,
+                command,
+                args,
+                cancelled: false,
+            };
+            events::emit(self, |events| &mut events.command, &mut event);
+            if event.cancelled {
+                return;
+            }
+
+            match event.command.as_str() {
+                "team" => self.team_chat(net_id, event.args),
+                "admin" => self.admin_chat(net_id, event.args),
+                "local" => self.local_chat(net_id, event.args),
+                "pm" => self.private_message(net_id, event.args),
+                "reply" | "r" => self.reply(net_id, event.args),
+                "ignore" => self.set_ignoring(net_id, event.args, true),
+                "unignore" => self.set_ignoring(net_id, event.args, false),
+                "topprint" | "centerprint" | "bottomprint" => {
+                    self.admin_print(net_id, &event.command, event.args)
+                }
+                _ => {}
+            }
             return;
         }
 
-        let packet =
-            packet_builder::build_message_packet(format!("\\c6 {}: \\c0{}", username, args));
+        let mut event = events::Chat {
+            net_id,
+            message: args,
+            cancelled: false,
+        };
+        events::emit(self, |events| &mut events.chat, &mut event);
+        if event.cancelled {
+            return;
+        }
+
+        let packet = packet_builder::build_message_packet(format!(
+            "{}: \\c0{}",
+            self.chat_name(net_id),
+            message::escape(&event.message)
+        ));
+        self.send_chat(net_id, &packet, |_| true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn vector(x: f32, y: f32, z: f32) -> Vector3 {
+        return Vector3 { x, y, z };
+    }
+
+    // A game with one 4x4 brick at the origin and a player standing off to the side of it
+    fn game_with_brick() -> Game {
+        let mut game = new();
+        game.bricks.push(Brick {
+            net_id: 1,
+            scale: vector(4.0, 4.0, 1.0),
+            collision: true,
+            ..Brick::default()
+        });
+        game.index_bricks();
+
+        let mut plr = player::new();
+        plr.net_id = 7;
+        plr.position = vector(20.0, 20.0, 0.0);
+        game.players.push(plr);
+
+        return game;
+    }
+
+    fn position_packet(x: f32, y: f32, z: f32, rotation: f32) -> Buffer {
+        let mut packet = buffer::new(None);
+        for value in [x, y, z, rotation] {
+            packet.write_float32(value);
+        }
+        return packet;
+    }
+
+    #[tokio::test]
+    async fn drops_non_finite_positions() {
+        let mut game = game_with_brick();
+
+        game.handle_packet(7, 2, position_packet(1.0, 2.0, 3.0, 90.0));
+        assert_eq!(game.players[0].position, vector(1.0, 2.0, 3.0));
+
+        game.handle_packet(7, 2, position_packet(f32::NAN, 0.0, 0.0, 0.0));
+        game.handle_packet(7, 2, position_packet(0.0, f32::INFINITY, 0.0, 0.0));
+        game.handle_packet(7, 2, position_packet(0.0, 0.0, f32::NEG_INFINITY, 0.0));
+        game.handle_packet(7, 2, position_packet(0.0, 0.0, 0.0, f32::NAN));
+        assert_eq!(game.players[0].position, vector(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn compressed_clicks_without_a_ray() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        // Input packet for a click with an empty key and nothing after it
+        encoder.write_all(&[6, 1, 0]).unwrap();
+
+        let mut packet = buffer::new(None);
+        packet.data = encoder.finish().unwrap();
+        assert!(packet.zlib_uncompress());
+        assert_eq!(packet.read_byte(), 6);
+
+        let position = vector(1.0, 2.0, 3.0);
+        assert_eq!(
+            read_input(&mut packet, position),
+            Some(Input::Click {
+                position,
+                direction: None
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn raycasts_end_on_bad_input() {
+        let game = game_with_brick();
+        let down = vector(0.0, 0.0, -1.0);
+
+        let hit = game.raycast(vector(2.0, 2.0, 10.0), down, f32::INFINITY);
+        assert_eq!(hit.map(|hit| hit.target), Some(Target::Brick(1)));
+
+        let up = vector(0.0, 0.0, 1.0);
+        assert!(game
+            .raycast(vector(2.0, 2.0, 10.0), up, f32::INFINITY)
+            .is_none());
+        assert!(game
+            .raycast(vector(f32::NAN, 2.0, 10.0), down, f32::INFINITY)
+            .is_none());
+        assert!(game
+            .raycast(vector(2.0, 2.0, 10.0), vector(0.0, f32::NAN, -1.0), 100.0)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn projectiles_from_nan_origins_are_dropped() {
+        let mut game = game_with_brick();
+        let tool = game.new_tool("Gun".to_string());
+        let tool_id = tool.net_id;
+        game.players[0].give_tool(tool);
+        game.players[0].equip(Some(tool_id));
+
+        let hits = Arc::new(Mutex::new(0));
+        let counted = hits.clone();
+        game.events
+            .on_projectile_hit(move |_, _| *counted.lock().unwrap() += 1);
+
+        let down = vector(0.0, 0.0, -1.0);
+        game.projectile(7, tool_id, vector(f32::NAN, 20.0, 1.0), down);
+        game.projectile(
+            7,
+            tool_id,
+            vector(20.0, 20.0, 1.0),
+            vector(f32::NAN, 0.0, 0.0),
+        );
+        assert_eq!(*hits.lock().unwrap(), 0);
+
+        // Still fine from where the player is standing
+        game.players[0].position = vector(2.0, 5.0, 1.0);
+        game.projectile(7, tool_id, vector(2.0, 4.0, 3.0), vector(0.0, -1.0, -1.0));
+        assert_eq!(*hits.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn team_colours_in_chat_leave_out_alpha() {
+        let mut game = game_with_brick();
+        let team = game.add_team("Red".to_string(), Color::from_rgba(255, 0, 0, 128));
+        game.players[0].team = Some(team);
+
+        assert_eq!(game.chat_name(7), "[#FF0000]Player");
+    }
+
+    // Adds a player whose packets land in the returned receiver instead of a socket
+    fn listening_player(game: &mut Game, net_id: u32) -> mpsc::Receiver<Buffer> {
+        let (sender, receiver) = mpsc::channel(16);
+        let mut plr = player::new();
+        plr.net_id = net_id;
+        plr.queue = Some(sender);
+        game.players.push(plr);
+
+        return receiver;
+    }
+
+    // The text of every chat packet waiting in `packets`
+    fn chat_lines(packets: &mut mpsc::Receiver<Buffer>) -> Vec<String> {
+        let mut lines = vec![];
+        while let Ok(packet) = packets.try_recv() {
+            let mut data = packet.data;
+            let mut message = buffer::next_message(&mut data).unwrap().unwrap();
+            if message.read_byte() == 6 {
+                lines.push(message.read_string());
+            }
+        }
+        return lines;
+    }
+
+    #[tokio::test]
+    async fn chat_handlers_rewrite_and_cancel() {
+        let mut game = new();
+        let _sender = listening_player(&mut game, 1);
+        let mut listener = listening_player(&mut game, 2);
+
+        game.events.on_chat(|_, event| {
+            event.message = event.message.to_uppercase();
+        });
+        game.events.on_chat(|_, event| {
+            if event.message.contains("SECRET") {
+                event.cancelled = true;
+            }
+        });
+        let reached = Arc::new(Mutex::new(vec![]));
+        let seen = reached.clone();
+        game.events.on_chat(move |_, event| {
+            seen.lock().unwrap().push(event.message.clone());
+        });
+
+        game.chatted(1, "chat".to_string(), "hello".to_string());
+        assert_eq!(chat_lines(&mut listener), ["\\c6 Player: \\c0HELLO"]);
+
+        game.chatted(1, "chat".to_string(), "a secret".to_string());
+        assert!(chat_lines(&mut listener).is_empty());
+
+        // The handler after the cancelling one only ever saw the first message
+        assert_eq!(*reached.lock().unwrap(), ["HELLO"]);
+    }
+
+    #[tokio::test]
+    async fn touches_start_and_end() {
+        let mut game = game_with_brick();
+        let log = Arc::new(Mutex::new(vec![]));
+
+        let touched = log.clone();
+        game.events.on_brick_touched(move |_, event| {
+            touched
+                .lock()
+                .unwrap()
+                .push(format!("touched {} {}", event.net_id, event.brick));
+        });
+        let ended = log.clone();
+        game.events.on_brick_touch_ended(move |_, event| {
+            ended
+                .lock()
+                .unwrap()
+                .push(format!("ended {} {}", event.net_id, event.brick));
+        });
 
-        drop(username);
+        game.tick();
+        assert!(log.lock().unwrap().is_empty());
 
-        self.broadcast_packet(&packet).await;
+        // Standing on top of it, which only shares a face
+        game.players[0].position = vector(2.0, 2.0, 1.0);
+        game.tick();
+        game.tick();
+        assert_eq!(*log.lock().unwrap(), ["touched 7 1"]);
 
-        drop(packet);
+        game.players[0].position = vector(20.0, 20.0, 1.0);
+        game.tick();
+        assert_eq!(*log.lock().unwrap(), ["touched 7 1", "ended 7 1"]);
     }
 }
@@ -0,0 +1,19 @@
+#![allow(clippy::needless_return)]
+
+pub mod avatar;
+pub mod bot;
+pub mod brick;
+pub mod buffer;
+pub mod camera;
+pub mod client;
+pub mod collision;
+pub mod colour;
+pub mod events;
+pub mod game;
+pub mod listing;
+pub mod message;
+pub mod packet_builder;
+pub mod player;
+pub mod team;
+pub mod timer;
+pub mod tool;
@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::game::Event;
+
+pub const POST_SERVER_URL: &str = "https://api.brick-hill.com/v1/games/postServer";
+pub const POST_INTERVAL: Duration = Duration::from_secs(60);
+
+// Kept well under the post interval, so a listing that never answers counts as a failure
+pub const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub host_key: String,
+    pub port: u16,
+    pub players: u32,
+    pub map: String,
+    pub brick_count: u32,
+}
+
+// Anything the heartbeat can be posted to, so the listing can be swapped out
+pub trait Listing {
+    fn post(&self, heartbeat: &Heartbeat) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+pub struct HttpListing {
+    pub url: String,
+
+    client: reqwest::Client,
+}
+
+pub fn new(url: String) -> HttpListing {
+    return HttpListing {
+        url,
+        client: reqwest::Client::builder()
+            .timeout(POST_TIMEOUT)
+            .build()
+            .expect("Couldn't build the listing's HTTP client"),
+    };
+}
+
+impl Listing for HttpListing {
+    async fn post(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        let body = serde_json::json!({
+            "host_key": heartbeat.host_key,
+            "port": heartbeat.port,
+            "players": heartbeat.players,
+            "map": heartbeat.map,
+            "brick_count": heartbeat.brick_count,
+        });
+
+        let response = match self.client.post(&self.url).json(&body).send().await {
+            Ok(response) => response,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if !response.status().is_success() {
+            return Err(format!("listing responded with {}", response.status()));
+        }
+
+        return Ok(());
+    }
+}
+
+// Posts a heartbeat every `interval`, backing off exponentially while the listing is failing
+pub async fn run<L: Listing>(listing: L, events: mpsc::Sender<Event>, interval: Duration) {
+    let mut retry = RETRY_MIN;
+
+    loop {
+        let (reply, heartbeat) = oneshot::channel();
+        if events.send(Event::Listing(reply)).await.is_err() {
+            return;
+        }
+        let Ok(heartbeat) = heartbeat.await else {
+            return;
+        };
+
+        match listing.post(&heartbeat).await {
+            Ok(_) => {
+                retry = RETRY_MIN;
+                tokio::time::sleep(interval).await;
+            }
+            Err(e) => {
+                println!(
+                    "Failed to post heartbeat: {} (retrying in {}s)",
+                    e,
+                    retry.as_secs()
+                );
+                tokio::time::sleep(retry).await;
+                retry = (retry * 2).min(RETRY_MAX);
+            }
+        }
+    }
+}
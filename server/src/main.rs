@@ -1,94 +1,75 @@
-use std::io::{self};
-use std::sync::Arc;
+use std::env;
+use std::time::Duration;
 
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
 
-mod brick;
-mod buffer;
-mod colour;
-mod game;
-mod packet_builder;
-mod player;
+use server::{brick, client, game, listing};
 
-async fn handle_client(_stream: TcpStream, game: Arc<Mutex<game::Game>>) -> Result<(), ()> {
-    let mut data = [0 as u8; 80];
+const EVENT_BACKLOG: usize = 1024;
 
-    let stream = Arc::new(Mutex::new(_stream));
-    let mut player: Arc<Mutex<player::Player>> = Arc::new(Mutex::new(player::new()));
-
-    loop {
-        let unlocked_stream = stream.lock().await;
-        match unlocked_stream.try_read(&mut data) {
-            Ok(0) => break,
-            Ok(_) => {
-                drop(unlocked_stream);
-
-                let mut buffer = buffer::new(Some(data));
-                buffer.read_uint_v();
-                buffer.zlib_uncompress();
-
-                if buffer.data.len() == 0 {
-                    continue;
-                }
-
-                let packet_type = buffer.read_byte();
-
-                if packet_type == 18 {
-                    continue;
-                }
-
-                {
-                    let result_lock = game.try_lock();
-                    let mut locked_game = result_lock.unwrap();
-
-                    if packet_type == 1 {
-                        let mut temp_player = player::new();
-                        temp_player.net_id = locked_game.new_net_object();
-
-                        player = Arc::new(Mutex::new(temp_player));
-
-                        let mut locked_player = player.lock().await;
-                        locked_player.set_stream(stream.clone());
-                        locked_player
-                            .check_auth(&mut buffer, &mut locked_game)
-                            .await;
-                        drop(locked_player);
-
-                        (*locked_game).add_player(player.clone()).await;
-                    } else if packet_type == 3 {
-                        let command = buffer.read_string();
-                        let args = buffer.read_string();
+#[tokio::main]
+async fn main() -> Result<(), ()> {
+    let mut new_game = game::new();
+    if let Ok(host_key) = env::var("HOST_KEY") {
+        new_game.host_key = host_key;
+    }
+    if let Ok(port) = env::var("PORT") {
+        new_game.port = port
+            .parse::<u16>()
+            .expect("PORT must be a valid port number");
+    }
+    if let Ok(timeout) = env::var("HEARTBEAT_TIMEOUT") {
+        let seconds = timeout
+            .parse::<u64>()
+            .expect("HEARTBEAT_TIMEOUT must be a number of seconds");
+        new_game.heartbeat_timeout = Duration::from_secs(seconds);
+    }
+    if let Ok(backlog) = env::var("SEND_BACKLOG") {
+        new_game.send_backlog = backlog
+            .parse::<usize>()
+            .expect("SEND_BACKLOG must be a number of packets");
+    }
+    if let Ok(tick_rate) = env::var("TICK_RATE") {
+        new_game.tick_rate = tick_rate
+            .parse::<u32>()
+            .expect("TICK_RATE must be a number of ticks per second");
+    }
+    if let Ok(delay) = env::var("RESPAWN_DELAY") {
+        let seconds = delay
+            .parse::<u64>()
+            .expect("RESPAWN_DELAY must be a number of seconds");
+        new_game.respawn_delay = Duration::from_secs(seconds);
+    }
+    if let Ok(timeout) = env::var("AFK_TIMEOUT") {
+        let seconds = timeout
+            .parse::<u64>()
+            .expect("AFK_TIMEOUT must be a number of seconds");
+        new_game.afk_timeout = Some(Duration::from_secs(seconds));
+    }
 
-                        let locked_player = player.lock().await;
-                        let net_id = locked_player.net_id;
-                        drop(locked_player);
+    let port = new_game.port;
+    let listener = TcpListener::bind(("0.0.0.0", port)).await.unwrap();
+    println!("Server listening on port {}", port);
 
-                        (*locked_game).chatted(net_id, command, args).await;
-                    }
-                }
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                continue;
-            }
-            Err(_) => {
-                return Ok(());
-            }
-        }
+    if let Some(map) = brick::load_from_file(new_game.map.clone()) {
+        new_game.load_map(map);
     }
 
-    println!("Client has disconnected");
-    Ok(())
-}
+    let host_key_set = !new_game.host_key.is_empty();
+    let auth_timeout = new_game.heartbeat_timeout;
 
-#[tokio::main]
-async fn main() -> Result<(), ()> {
-    let listener = TcpListener::bind("0.0.0.0:42480").await.unwrap();
-    println!("Server listening on port 42480");
-
-    let game = Arc::new(Mutex::new(game::new()));
+    let (events, receiver) = mpsc::channel(EVENT_BACKLOG);
+    tokio::spawn(game::run(new_game, receiver));
 
-    brick::load_from_file("map.brk".to_string());
+    if host_key_set {
+        let listing = listing::new(listing::POST_SERVER_URL.to_string());
+        tokio::spawn(listing::run(
+            listing,
+            events.clone(),
+            listing::POST_INTERVAL,
+        ));
+    }
 
     loop {
         let stream = match listener.accept().await {
@@ -100,8 +81,8 @@ async fn main() -> Result<(), ()> {
 
         println!("New connection: {}", stream.peer_addr().unwrap());
 
-        let game_clone = Arc::clone(&game);
+        let events_clone = events.clone();
 
-        tokio::spawn(async move { handle_client(stream, game_clone).await });
+        tokio::spawn(async move { client::handle(stream, events_clone, auth_timeout).await });
     }
 }
@@ -0,0 +1,95 @@
+use crate::colour::Color;
+
+// Chat text picks up colours from `\cN` presets (0-9) and `[#RRGGBB]` hex codes. Text from
+// players goes through `escape` so it can't change the colours around it.
+pub struct Message {
+    text: String,
+}
+
+pub fn new() -> Message {
+    return Message {
+        text: String::new(),
+    };
+}
+
+// Breaks up anything the client would read as a colour code with a zero width space
+pub fn escape(text: &str) -> String {
+    return text
+        .replace("\\c", "\\\u{200B}c")
+        .replace("[#", "[\u{200B}#");
+}
+
+pub fn colour_code(colour: &Color) -> String {
+    return format!(
+        "[#{:02X}{:02X}{:02X}]",
+        colour.red, colour.green, colour.blue
+    );
+}
+
+impl Message {
+    pub fn colour(mut self, colour: &Color) -> Message {
+        self.text.push_str(&colour_code(colour));
+        return self;
+    }
+
+    // One of the client's ten preset colours, anything above 9 is clamped
+    pub fn preset(mut self, preset: u8) -> Message {
+        self.text.push_str(&format!("\\c{}", preset.min(9)));
+        return self;
+    }
+
+    pub fn text(mut self, text: &str) -> Message {
+        self.text.push_str(&escape(text));
+        return self;
+    }
+
+    // Appended as it is, colour codes and all
+    pub fn raw(mut self, text: &str) -> Message {
+        self.text.push_str(text);
+        return self;
+    }
+
+    pub fn build(self) -> String {
+        return self.text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_colour_codes() {
+        assert_eq!(escape("\\c5red"), "\\\u{200B}c5red");
+        assert_eq!(escape("[#FF0000]red"), "[\u{200B}#FF0000]red");
+        assert_eq!(
+            escape("a [link] and \\ back\\slash"),
+            "a [link] and \\ back\\slash"
+        );
+    }
+
+    #[test]
+    fn colour_codes_leave_out_alpha() {
+        assert_eq!(colour_code(&Color::from(0x12AB00)), "[#12AB00]");
+        assert_eq!(colour_code(&Color::from_rgba(1, 2, 3, 4)), "[#010203]");
+    }
+
+    #[test]
+    fn presets_are_clamped() {
+        assert_eq!(new().preset(3).build(), "\\c3");
+        assert_eq!(new().preset(9).build(), "\\c9");
+        assert_eq!(new().preset(200).build(), "\\c9");
+    }
+
+    #[test]
+    fn text_is_escaped_and_raw_is_not() {
+        let message = new()
+            .colour(&Color::from(0xFF0000))
+            .text("[#00FF00]hi")
+            .preset(0)
+            .raw("[#0000FF]ok")
+            .build();
+
+        assert_eq!(message, "[#FF0000][\u{200B}#00FF00]hi\\c0[#0000FF]ok");
+    }
+}
@@ -1,4 +1,12 @@
-use crate::buffer;
+use crate::{
+    avatar::Avatar,
+    bot::Bot,
+    brick::{Brick, Vector3},
+    buffer,
+    camera::{CameraTarget, CameraType},
+    team::Team,
+    tool::Tool,
+};
 
 pub fn build_auth_packet(
     user_id: u32,
@@ -28,3 +36,319 @@ pub fn build_message_packet(message: String) -> buffer::Buffer {
 
     return packet;
 }
+
+pub fn build_remove_player_packet(net_id: u32) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(5);
+    packet.write_uint32(net_id);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// A single property change sent through the PlayerModification packet
+pub enum Modification {
+    Kick(String),
+    Speed(f32),
+    JumpPower(f32),
+    Gravity(f32),
+    Scale(Vector3),
+    CanMove(bool),
+    CameraFov(f32),
+    CameraDistance(f32),
+    CameraType(CameraType),
+    CameraPosition(Vector3),
+    CameraRotation(Vector3),
+    CameraTarget(CameraTarget),
+    // Net id of the tool to hold, 0 to put it away
+    EquipTool(u32),
+    // Text shown across the screen for a number of seconds
+    TopPrint(String, u32),
+    CenterPrint(String, u32),
+    BottomPrint(String, u32),
+    // The scoreboard's column names, in order
+    Leaderstats(Vec<String>),
+    // A player's value in one scoreboard column, by net id so it can go to everyone
+    Score(u32, String, i32),
+}
+
+pub fn build_modification_packet(modification: Modification) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(7);
+
+    match modification {
+        Modification::Kick(message) => {
+            packet.write_string(String::from("kick"));
+            packet.write_string(message);
+        }
+        Modification::Speed(speed) => {
+            packet.write_string(String::from("speed"));
+            packet.write_float32(speed);
+        }
+        Modification::JumpPower(jump_power) => {
+            packet.write_string(String::from("jumpPower"));
+            packet.write_float32(jump_power);
+        }
+        Modification::Gravity(gravity) => {
+            packet.write_string(String::from("gravity"));
+            packet.write_float32(gravity);
+        }
+        Modification::Scale(scale) => {
+            packet.write_string(String::from("scale"));
+            packet.write_float32(scale.x);
+            packet.write_float32(scale.y);
+            packet.write_float32(scale.z);
+        }
+        Modification::CanMove(can_move) => {
+            packet.write_string(String::from("canMove"));
+            packet.write_byte(can_move as u8);
+        }
+        Modification::CameraFov(fov) => {
+            packet.write_string(String::from("camFOV"));
+            packet.write_float32(fov);
+        }
+        Modification::CameraDistance(distance) => {
+            packet.write_string(String::from("camDistance"));
+            packet.write_float32(distance);
+        }
+        Modification::CameraType(camera_type) => {
+            packet.write_string(String::from("camType"));
+            packet.write_string(String::from(camera_type.name()));
+        }
+        Modification::CameraPosition(position) => {
+            packet.write_string(String::from("camPos"));
+            packet.write_float32(position.x);
+            packet.write_float32(position.y);
+            packet.write_float32(position.z);
+        }
+        Modification::CameraRotation(rotation) => {
+            packet.write_string(String::from("camRot"));
+            packet.write_float32(rotation.x);
+            packet.write_float32(rotation.y);
+            packet.write_float32(rotation.z);
+        }
+        Modification::EquipTool(net_id) => {
+            packet.write_string(String::from("equipTool"));
+            packet.write_uint32(net_id);
+        }
+        Modification::TopPrint(message, seconds) => {
+            packet.write_string(String::from("topPrint"));
+            packet.write_string(message);
+            packet.write_uint32(seconds);
+        }
+        Modification::CenterPrint(message, seconds) => {
+            packet.write_string(String::from("centerPrint"));
+            packet.write_string(message);
+            packet.write_uint32(seconds);
+        }
+        Modification::BottomPrint(message, seconds) => {
+            packet.write_string(String::from("bottomPrint"));
+            packet.write_string(message);
+            packet.write_uint32(seconds);
+        }
+        Modification::Leaderstats(columns) => {
+            packet.write_string(String::from("leaderstats"));
+            packet.write_byte(columns.len() as u8);
+            for column in columns {
+                packet.write_string(column);
+            }
+        }
+        Modification::Score(net_id, column, value) => {
+            packet.write_string(String::from("score"));
+            packet.write_uint32(net_id);
+            packet.write_string(column);
+            packet.write_int32(value);
+        }
+        Modification::CameraTarget(target) => {
+            // Target kind (0 own figure, 1 player, 2 brick) followed by its net id
+            packet.write_string(String::from("camObj"));
+            match target {
+                CameraTarget::Own => {
+                    packet.write_byte(0);
+                    packet.write_uint32(0);
+                }
+                CameraTarget::Player(net_id) => {
+                    packet.write_byte(1);
+                    packet.write_uint32(net_id);
+                }
+                CameraTarget::Brick(net_id) => {
+                    packet.write_byte(2);
+                    packet.write_uint32(net_id);
+                }
+            }
+        }
+    }
+
+    packet.write_uint_v();
+
+    return packet;
+}
+
+pub fn build_figure_packet(net_id: u32, position: Vector3, rotation: f32) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(4);
+    packet.write_uint32(net_id);
+    packet.write_string(String::from("ABCF"));
+    packet.write_float32(position.x);
+    packet.write_float32(position.y);
+    packet.write_float32(position.z);
+    packet.write_float32(rotation);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+pub fn build_avatar_packet(net_id: u32, avatar: &Avatar) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(4);
+    packet.write_uint32(net_id);
+    packet.write_string(String::from("KLMNOPQRSTUVWX"));
+
+    packet.write_uint32(avatar.head_colour.into_bgr_u32());
+    packet.write_uint32(avatar.torso_colour.into_bgr_u32());
+    packet.write_uint32(avatar.left_arm_colour.into_bgr_u32());
+    packet.write_uint32(avatar.right_arm_colour.into_bgr_u32());
+    packet.write_uint32(avatar.left_leg_colour.into_bgr_u32());
+    packet.write_uint32(avatar.right_leg_colour.into_bgr_u32());
+
+    packet.write_uint32(avatar.face);
+    for hat in avatar.hats {
+        packet.write_uint32(hat);
+    }
+    packet.write_uint32(avatar.shirt);
+    packet.write_uint32(avatar.pants);
+    packet.write_uint32(avatar.tshirt);
+    packet.write_uint32(avatar.tool);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// Creates a bot, its avatar follows as a figure packet and it moves like any other figure
+pub fn build_bot_packet(bot: &Bot) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(12);
+    packet.write_uint32(bot.net_id);
+    packet.write_string(bot.name.clone());
+    packet.write_float32(bot.position.x);
+    packet.write_float32(bot.position.y);
+    packet.write_float32(bot.position.z);
+    packet.write_float32(bot.rotation);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+pub fn build_destroy_bot_packet(net_id: u32) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(15);
+    packet.write_uint32(net_id);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+pub fn build_bricks_packet(bricks: &[Brick]) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(17);
+    packet.write_uint32(bricks.len() as u32);
+
+    for brick in bricks {
+        packet.write_uint32(brick.net_id);
+        packet.write_float32(brick.position.x);
+        packet.write_float32(brick.position.y);
+        packet.write_float32(brick.position.z);
+
+        packet.write_float32(brick.scale.x);
+        packet.write_float32(brick.scale.y);
+        packet.write_float32(brick.scale.z);
+
+        packet.write_uint32(brick.colour.into_bgr_u32());
+        packet.write_float32(brick.visibility);
+
+        packet.write_byte(brick.clickable as u8);
+        packet.write_float32(brick.click_distance);
+    }
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// Kills the player when `dead`, otherwise brings them back
+pub fn build_kill_packet(net_id: u32, dead: bool) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(8);
+    packet.write_uint32(net_id);
+    packet.write_byte(dead as u8);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+pub fn build_team_packet(team: &Team) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(10);
+    packet.write_uint32(team.net_id);
+    packet.write_string(team.name.clone());
+    packet.write_uint32(team.colour.into_bgr_u32());
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// `team` is 0 when the player isn't on one
+pub fn build_team_figure_packet(net_id: u32, team: u32) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(4);
+    packet.write_uint32(net_id);
+    packet.write_string(String::from("Y"));
+    packet.write_uint32(team);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// The name over a player's head, whether it's shown, and their speech bubble (empty for none)
+pub fn build_name_figure_packet(
+    net_id: u32,
+    name: String,
+    name_tag: bool,
+    speech: String,
+) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(4);
+    packet.write_uint32(net_id);
+    packet.write_string(String::from("Zab"));
+    packet.write_string(name);
+    packet.write_byte(name_tag as u8);
+    packet.write_string(speech);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// Shows or hides a figure, only for whoever the packet is sent to
+pub fn build_visibility_figure_packet(net_id: u32, visible: bool) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(4);
+    packet.write_uint32(net_id);
+    packet.write_string(String::from("c"));
+    packet.write_byte(visible as u8);
+    packet.write_uint_v();
+
+    return packet;
+}
+
+// Adds the tool to the player's inventory, or removes it when `add` is false
+pub fn build_tool_packet(tool: &Tool, add: bool) -> buffer::Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(11);
+    packet.write_byte(add as u8);
+    packet.write_uint32(tool.net_id);
+    packet.write_string(tool.name.clone());
+    packet.write_uint32(tool.model);
+    packet.write_byte(tool.enabled as u8);
+    packet.write_uint_v();
+
+    return packet;
+}
@@ -1,32 +1,148 @@
-use crate::{buffer::Buffer, game::Game, packet_builder};
+use crate::{
+    avatar::{self, Avatar},
+    brick::Vector3,
+    buffer::Buffer,
+    camera::{CameraTarget, CameraType},
+    game::Game,
+    packet_builder::{self, Modification},
+    tool::Tool,
+};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf};
+
+const WRITE_BATCH: usize = 64;
 
 pub struct Player {
-    pub stream: Option<Arc<Mutex<TcpStream>>>,
+    // Outbound packets, drained onto the socket by the player's writer task
+    pub queue: Option<mpsc::Sender<Buffer>>,
 
     pub username: String,
     pub user_id: u32,
     pub net_id: u32,
     pub admin: bool,
     pub membership: u8,
+
+    pub avatar: Avatar,
+
+    // Keys the client sends mapped to the key scripts see, unbound keys pass through as they are
+    pub key_bindings: HashMap<String, String>,
+
+    pub tools: Vec<Tool>,
+    pub equipped: Option<u32>,
+
+    // Shown over the player's head instead of their username, colour codes included
+    pub display_name: Option<String>,
+    pub name_tag: bool,
+    pub speech: String,
+    // Set when any of the above changed since the last tick sent it out
+    pub name_changed: bool,
+
+    // Players this player can't see
+    pub hidden: Vec<u32>,
+    // Players whose chat this player doesn't get
+    pub ignored: Vec<u32>,
+    // Who "/reply" goes to, the last player this one messaged or was messaged by
+    pub reply_to: Option<u32>,
+
+    pub team: Option<u32>,
+    // Set when the team changed since the last tick sent it out
+    pub team_changed: bool,
+
+    // Scoreboard values by column name, anything missing counts as 0
+    pub scores: HashMap<String, i32>,
+    // Columns changed since the last tick sent them out
+    pub scores_changed: Vec<String>,
+
+    pub health: f32,
+    pub max_health: f32,
+    pub alive: bool,
+
+    // Movement settings live on the client, the setters below keep it in sync
+    pub speed: f32,
+    pub jump_power: f32,
+    pub gravity: f32,
+    pub scale: Vector3,
+    pub can_move: bool,
+    pub camera_fov: f32,
+    pub camera_distance: f32,
+    pub camera_type: CameraType,
+    pub camera_position: Vector3,
+    pub camera_rotation: Vector3,
+    pub camera_target: CameraTarget,
+
+    pub position: Vector3,
+    pub rotation: f32,
+    // Set when the figure changed since the last tick sent it out
+    pub moved: bool,
+    // Net ids of the bricks the player was inside last tick
+    pub touching: Vec<u32>,
+
+    pub connected: bool,
+    // Wakes the connection's read loop so it stops waiting on the socket
+    pub disconnected: Arc<Notify>,
+    pub last_heartbeat: Instant,
+    pub last_activity: Instant,
 }
 
 pub fn new() -> Player {
     return Player {
-        stream: None,
+        queue: None,
         username: String::from("Player"),
         user_id: 0,
         net_id: 0,
         admin: false,
         membership: 0,
+        avatar: avatar::new(),
+        key_bindings: HashMap::new(),
+        tools: vec![],
+        equipped: None,
+        display_name: None,
+        name_tag: true,
+        speech: String::new(),
+        name_changed: false,
+        hidden: vec![],
+        ignored: vec![],
+        reply_to: None,
+        team: None,
+        team_changed: false,
+        scores: HashMap::new(),
+        scores_changed: vec![],
+        health: 100.0,
+        max_health: 100.0,
+        alive: true,
+        speed: 4.0,
+        jump_power: 5.0,
+        gravity: 1.0,
+        scale: Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        can_move: true,
+        camera_fov: 60.0,
+        camera_distance: 5.0,
+        camera_type: CameraType::ThirdPerson,
+        camera_position: Vector3::default(),
+        camera_rotation: Vector3::default(),
+        camera_target: CameraTarget::Own,
+        position: Vector3::default(),
+        rotation: 0.0,
+        moved: false,
+        touching: vec![],
+        connected: true,
+        disconnected: Arc::new(Notify::new()),
+        last_heartbeat: Instant::now(),
+        last_activity: Instant::now(),
     };
 }
 
 impl Player {
-    pub async fn build_auth_packet(&mut self, brick_count: u32) -> Buffer {
+    pub fn build_auth_packet(&mut self, brick_count: u32) -> Buffer {
         return packet_builder::build_auth_packet(
             self.user_id,
             self.username.clone(),
@@ -37,33 +153,374 @@ impl Player {
         );
     }
 
-    pub fn set_stream(&mut self, stream: Arc<Mutex<TcpStream>>) {
-        self.stream = Some(stream);
+    // Hands the socket to a writer task, `backlog` is how many packets may queue up before the player is dropped
+    pub fn set_stream(&mut self, stream: OwnedWriteHalf, backlog: usize) {
+        let (sender, receiver) = mpsc::channel(backlog);
+        tokio::spawn(write_packets(stream, receiver));
+
+        self.queue = Some(sender);
     }
 
-    pub async fn send_packet(&mut self, buf: Buffer) {
-        let stream = self.stream.as_ref().unwrap();
-        let mut new_stream = (&stream).clone().lock().await;
+    pub fn send_packet(&mut self, buf: Buffer) {
+        if !self.connected {
+            return;
+        }
+        let Some(queue) = self.queue.as_ref() else {
+            return;
+        };
 
-        match new_stream.write(&buf.data).await {
-            Ok(size) => {
-                if size != buf.data.len() {
-                    println!("Failed to send all data: Size: {}", size);
-                }
+        match queue.try_send(buf) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                println!("{} is too slow to keep up, disconnecting", self.username);
+                self.disconnect();
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.disconnect();
             }
-            Err(e) => {
-                println!("Error sending packet: {:?}", e);
+        }
+    }
+
+    pub fn set_position(&mut self, position: Vector3, rotation: f32) {
+        if self.position == position && self.rotation == rotation {
+            return;
+        }
+
+        self.position = position;
+        self.rotation = rotation;
+        self.moved = true;
+    }
+
+    pub fn bind_key(&mut self, key: String, bound: String) {
+        self.key_bindings.insert(key, bound);
+    }
+
+    pub fn unbind_key(&mut self, key: &str) {
+        self.key_bindings.remove(key);
+    }
+
+    pub fn bound_key(&self, key: String) -> String {
+        return match self.key_bindings.get(&key) {
+            Some(bound) => bound.clone(),
+            None => key,
+        };
+    }
+
+    pub fn find_tool(&self, net_id: u32) -> Option<&Tool> {
+        return self.tools.iter().find(|tool| tool.net_id == net_id);
+    }
+
+    pub fn give_tool(&mut self, tool: Tool) {
+        if self.find_tool(tool.net_id).is_some() {
+            return;
+        }
+
+        self.send_packet(packet_builder::build_tool_packet(&tool, true));
+        self.tools.push(tool);
+    }
+
+    pub fn take_tool(&mut self, net_id: u32) {
+        let Some(index) = self.tools.iter().position(|tool| tool.net_id == net_id) else {
+            return;
+        };
+
+        if self.equipped == Some(net_id) {
+            self.equip(None);
+        }
+
+        let tool = self.tools.remove(index);
+        self.send_packet(packet_builder::build_tool_packet(&tool, false));
+    }
+
+    // Puts a tool from the inventory in the player's hand, None puts it away
+    pub fn equip(&mut self, net_id: Option<u32>) {
+        if let Some(net_id) = net_id {
+            match self.find_tool(net_id) {
+                Some(tool) if tool.enabled => {}
+                _ => return,
             }
         }
+
+        if self.equipped == net_id {
+            return;
+        }
+        self.equipped = net_id;
+        self.modify(Modification::EquipTool(net_id.unwrap_or(0)));
+    }
+
+    pub fn set_tool_enabled(&mut self, net_id: u32, enabled: bool) {
+        let Some(tool) = self.tools.iter_mut().find(|tool| tool.net_id == net_id) else {
+            return;
+        };
+        if tool.enabled == enabled {
+            return;
+        }
+        tool.enabled = enabled;
+
+        // Resending the tool updates it in place
+        let packet = packet_builder::build_tool_packet(tool, true);
+        self.send_packet(packet);
+
+        if !enabled && self.equipped == Some(net_id) {
+            self.equip(None);
+        }
+    }
+
+    pub fn shown_name(&self) -> String {
+        return match &self.display_name {
+            Some(name) => name.clone(),
+            None => self.username.clone(),
+        };
+    }
+
+    // The game shows everyone the changes below on its next tick
+    pub fn set_display_name(&mut self, name: Option<String>) {
+        if self.display_name == name {
+            return;
+        }
+        self.display_name = name;
+        self.name_changed = true;
+    }
+
+    pub fn set_name_tag(&mut self, shown: bool) {
+        if self.name_tag == shown {
+            return;
+        }
+        self.name_tag = shown;
+        self.name_changed = true;
+    }
+
+    // An empty message takes the bubble down
+    pub fn set_speech(&mut self, message: String) {
+        if self.speech == message {
+            return;
+        }
+        self.speech = message;
+        self.name_changed = true;
+    }
+
+    // Hides another player from this one only, e.g. for spectators
+    pub fn hide_player(&mut self, net_id: u32) {
+        if net_id == self.net_id || self.hidden.contains(&net_id) {
+            return;
+        }
+        self.hidden.push(net_id);
+        self.send_packet(packet_builder::build_visibility_figure_packet(
+            net_id, false,
+        ));
+    }
+
+    pub fn show_player(&mut self, net_id: u32) {
+        let Some(index) = self.hidden.iter().position(|hidden| *hidden == net_id) else {
+            return;
+        };
+        self.hidden.remove(index);
+        self.send_packet(packet_builder::build_visibility_figure_packet(net_id, true));
+    }
+
+    pub fn ignore(&mut self, net_id: u32) {
+        if net_id != self.net_id && !self.ignored.contains(&net_id) {
+            self.ignored.push(net_id);
+        }
     }
 
-    pub async fn check_auth(&mut self, _buf: &mut Buffer, game: &mut MutexGuard<'_, Game>) {
-        if (*game).is_local {
-            self.username = String::from(format!("Player {}", game.players.len() + 1));
+    pub fn unignore(&mut self, net_id: u32) {
+        self.ignored.retain(|ignored| *ignored != net_id);
+    }
 
-            let packet = self.build_auth_packet(game.brick_count).await;
+    pub fn is_ignoring(&self, net_id: u32) -> bool {
+        return self.ignored.contains(&net_id);
+    }
 
-            self.send_packet(packet).await;
+    // Takes a team's net id, the game shows everyone on its next tick
+    pub fn set_team(&mut self, team: Option<u32>) {
+        if self.team == team {
+            return;
+        }
+        self.team = team;
+        self.team_changed = true;
+    }
+
+    pub fn score(&self, column: &str) -> i32 {
+        return self.scores.get(column).copied().unwrap_or(0);
+    }
+
+    // The game shows everyone the new value on its next tick
+    pub fn set_score(&mut self, column: &str, value: i32) {
+        if self.score(column) == value {
+            return;
+        }
+        self.scores.insert(column.to_string(), value);
+
+        if !self.scores_changed.iter().any(|changed| changed == column) {
+            self.scores_changed.push(column.to_string());
+        }
+    }
+
+    pub fn add_score(&mut self, column: &str, amount: i32) {
+        self.set_score(column, self.score(column).saturating_add(amount));
+    }
+
+    pub fn set_health(&mut self, health: f32) {
+        self.health = health.clamp(0.0, self.max_health);
+    }
+
+    // The game notices on its next tick and takes care of the death and respawn
+    pub fn kill(&mut self) {
+        self.health = 0.0;
+    }
+
+    pub fn modify(&mut self, modification: Modification) {
+        let packet = packet_builder::build_modification_packet(modification);
+        self.send_packet(packet);
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        if self.speed == speed {
+            return;
+        }
+        self.speed = speed;
+        self.modify(Modification::Speed(speed));
+    }
+
+    pub fn set_jump_power(&mut self, jump_power: f32) {
+        if self.jump_power == jump_power {
+            return;
+        }
+        self.jump_power = jump_power;
+        self.modify(Modification::JumpPower(jump_power));
+    }
+
+    pub fn set_gravity(&mut self, gravity: f32) {
+        if self.gravity == gravity {
+            return;
+        }
+        self.gravity = gravity;
+        self.modify(Modification::Gravity(gravity));
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3) {
+        if self.scale == scale {
+            return;
+        }
+        self.scale = scale;
+        self.modify(Modification::Scale(scale));
+    }
+
+    pub fn set_can_move(&mut self, can_move: bool) {
+        if self.can_move == can_move {
+            return;
+        }
+        self.can_move = can_move;
+        self.modify(Modification::CanMove(can_move));
+    }
+
+    pub fn set_camera_fov(&mut self, fov: f32) {
+        if self.camera_fov == fov {
+            return;
+        }
+        self.camera_fov = fov;
+        self.modify(Modification::CameraFov(fov));
+    }
+
+    pub fn set_camera_distance(&mut self, distance: f32) {
+        if self.camera_distance == distance {
+            return;
+        }
+        self.camera_distance = distance;
+        self.modify(Modification::CameraDistance(distance));
+    }
+
+    pub fn set_camera_type(&mut self, camera_type: CameraType) {
+        if self.camera_type == camera_type {
+            return;
+        }
+        self.camera_type = camera_type;
+        self.modify(Modification::CameraType(camera_type));
+    }
+
+    pub fn set_camera_position(&mut self, position: Vector3) {
+        if self.camera_position == position {
+            return;
+        }
+        self.camera_position = position;
+        self.modify(Modification::CameraPosition(position));
+    }
+
+    pub fn set_camera_rotation(&mut self, rotation: Vector3) {
+        if self.camera_rotation == rotation {
+            return;
+        }
+        self.camera_rotation = rotation;
+        self.modify(Modification::CameraRotation(rotation));
+    }
+
+    // Points the camera at another player or a brick, e.g. for spectating
+    pub fn set_camera_target(&mut self, target: CameraTarget) {
+        if self.camera_target == target {
+            return;
+        }
+        self.camera_target = target;
+        self.modify(Modification::CameraTarget(target));
+    }
+
+    // Puts the camera somewhere fixed for a cutscene
+    pub fn fix_camera(&mut self, position: Vector3, rotation: Vector3) {
+        self.set_camera_type(CameraType::Fixed);
+        self.set_camera_position(position);
+        self.set_camera_rotation(rotation);
+    }
+
+    // Hands the camera back to the player
+    pub fn reset_camera(&mut self) {
+        self.set_camera_target(CameraTarget::Own);
+        self.set_camera_type(CameraType::ThirdPerson);
+    }
+
+    pub fn heartbeat(&mut self) {
+        self.last_heartbeat = Instant::now();
+    }
+
+    // Movement and chat count as activity, heartbeats only prove the connection is alive
+    pub fn active(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    // Sends a line to this player's chat box
+    pub fn message(&mut self, text: String) {
+        self.send_packet(packet_builder::build_message_packet(text));
+    }
+
+    pub fn top_print(&mut self, message: String, seconds: u32) {
+        self.modify(Modification::TopPrint(message, seconds));
+    }
+
+    pub fn center_print(&mut self, message: String, seconds: u32) {
+        self.modify(Modification::CenterPrint(message, seconds));
+    }
+
+    pub fn bottom_print(&mut self, message: String, seconds: u32) {
+        self.modify(Modification::BottomPrint(message, seconds));
+    }
+
+    pub fn kick(&mut self, message: String) {
+        self.modify(Modification::Kick(message));
+
+        self.disconnect();
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connected = false;
+        self.disconnected.notify_one();
+    }
+
+    pub fn check_auth(&mut self, _buf: &mut Buffer, game: &Game) {
+        if game.is_local {
+            self.username = format!("Player {}", game.players.len() + 1);
+
+            let packet = self.build_auth_packet(game.brick_count);
+
+            self.send_packet(packet);
 
             return;
         }
@@ -71,3 +528,21 @@ impl Player {
         // let url = format!("https://api.brick-hill.com/v1/auth/verifyToken?token=${}&host_key=${}", token, "");
     }
 }
+
+async fn write_packets(mut stream: OwnedWriteHalf, mut queue: mpsc::Receiver<Buffer>) {
+    let mut packets = Vec::new();
+    let mut data = Vec::new();
+
+    // Everything that queued up while the last write was in flight goes out in one go
+    while queue.recv_many(&mut packets, WRITE_BATCH).await > 0 {
+        for buf in packets.drain(..) {
+            data.extend_from_slice(&buf.data);
+        }
+
+        if let Err(e) = stream.write_all(&data).await {
+            println!("Error sending packet: {:?}", e);
+            break;
+        }
+        data.clear();
+    }
+}
@@ -0,0 +1,16 @@
+use crate::colour::Color;
+
+#[derive(Debug, Clone)]
+pub struct Team {
+    pub net_id: u32,
+    pub name: String,
+    pub colour: Color,
+}
+
+pub fn new(name: String, colour: Color) -> Team {
+    return Team {
+        net_id: 0,
+        name,
+        colour,
+    };
+}
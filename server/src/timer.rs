@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::game::Game;
+
+pub type Callback = Box<dyn FnMut(&mut Game) + Send>;
+
+pub struct Timer {
+    pub id: u32,
+    pub due: Instant,
+    // Repeating timers are rescheduled from when they were due, not when they ran, so they don't drift
+    pub period: Option<Duration>,
+    pub callback: Callback,
+}
+
+pub fn new(id: u32, delay: Duration, period: Option<Duration>, callback: Callback) -> Timer {
+    return Timer {
+        id,
+        due: Instant::now() + delay,
+        period,
+        callback,
+    };
+}
+
+impl Timer {
+    // Fires the callback and returns whether the timer should stay scheduled
+    pub fn fire(&mut self, game: &mut Game, now: Instant) -> bool {
+        (self.callback)(game);
+
+        let Some(period) = self.period else {
+            return false;
+        };
+
+        self.due += period;
+        if self.due <= now {
+            // Fell behind by more than a period, skip the missed runs
+            self.due = now + period;
+        }
+
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use crate::game;
+
+    fn counter() -> (Arc<AtomicU32>, impl FnMut(&mut game::Game) + Send + 'static) {
+        let count = Arc::new(AtomicU32::new(0));
+        let counted = count.clone();
+        return (count, move |_: &mut game::Game| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_fires_each_period() {
+        let mut game = game::new();
+        let (count, callback) = counter();
+        game.every(Duration::from_secs(1), callback);
+
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        for expected in 1..=3 {
+            time::advance(Duration::from_secs(1)).await;
+            game.tick();
+            assert_eq!(count.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn after_fires_once() {
+        let mut game = game::new();
+        let (count, mut callback) = counter();
+        game.after(Duration::from_secs(2), move |game| callback(game));
+
+        time::advance(Duration::from_secs(1)).await;
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        time::advance(Duration::from_secs(1)).await;
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        time::advance(Duration::from_secs(5)).await;
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(game.timers.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_from_inside_a_callback() {
+        let mut game = game::new();
+        let id = Arc::new(AtomicU32::new(0));
+        let count = Arc::new(AtomicU32::new(0));
+
+        let (own_id, counted) = (id.clone(), count.clone());
+        let timer = game.every(Duration::from_secs(1), move |game| {
+            if counted.fetch_add(1, Ordering::SeqCst) == 1 {
+                game.cancel(own_id.load(Ordering::SeqCst));
+            }
+        });
+        id.store(timer, Ordering::SeqCst);
+
+        // Cancels one that's still waiting to run this tick, too
+        let other = Arc::new(AtomicU32::new(0));
+        let other_id = other.clone();
+        game.after(Duration::from_millis(1500), move |game| {
+            game.cancel(other_id.load(Ordering::SeqCst));
+        });
+        let (other_count, callback) = counter();
+        other.store(
+            game.every(Duration::from_secs(1), callback),
+            Ordering::SeqCst,
+        );
+
+        for _ in 0..5 {
+            time::advance(Duration::from_secs(1)).await;
+            game.tick();
+        }
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert_eq!(other_count.load(Ordering::SeqCst), 1);
+        assert!(game.timers.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_missed_periods() {
+        let mut game = game::new();
+        let (count, callback) = counter();
+        game.every(Duration::from_secs(1), callback);
+
+        // A stall of several periods runs the timer once rather than in a burst
+        time::advance(Duration::from_millis(4500)).await;
+        game.tick();
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Then carries on a full period from when it caught up
+        time::advance(Duration::from_millis(900)).await;
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        time::advance(Duration::from_millis(100)).await;
+        game.tick();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,18 @@
+#[derive(Debug, Clone)]
+pub struct Tool {
+    pub net_id: u32,
+    pub name: String,
+    // Asset id of the model held in hand, 0 for none
+    pub model: u32,
+    // Disabled tools stay in the inventory but can't be equipped or activated
+    pub enabled: bool,
+}
+
+pub fn new(name: String) -> Tool {
+    return Tool {
+        net_id: 0,
+        name,
+        model: 0,
+        enabled: true,
+    };
+}
@@ -0,0 +1,25 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use server::{client, game};
+use tokio::{net::TcpListener, sync::mpsc};
+
+// Runs a game with no map on a free local port, accepting connections the way main does
+pub async fn start(game: game::Game, auth_timeout: Duration) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let (events, receiver) = mpsc::channel(1024);
+    tokio::spawn(game::run(game, receiver));
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            tokio::spawn(client::handle(stream, events.clone(), auth_timeout));
+        }
+    });
+
+    return address;
+}
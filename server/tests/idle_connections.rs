@@ -0,0 +1,48 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::time::Duration;
+
+use server::game;
+use tokio::net::TcpStream;
+
+const CONNECTIONS: usize = 100;
+const WINDOW: Duration = Duration::from_secs(2);
+
+// User and system CPU time used by this process so far. /proc reports it in ticks of 1/100s.
+#[cfg(target_os = "linux")]
+fn cpu_time() -> Duration {
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+    // The process name can contain spaces, so count fields from after it
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+    let user: u64 = fields[11].parse().unwrap();
+    let system: u64 = fields[12].parse().unwrap();
+
+    return Duration::from_millis((user + system) * 10);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn idle_connections_do_not_use_cpu() {
+    let address = common::start(game::new(), Duration::from_secs(60)).await;
+
+    // None of these ever send anything, so their reads should just sit there
+    let mut streams: Vec<TcpStream> = vec![];
+    for _ in 0..CONNECTIONS {
+        streams.push(TcpStream::connect(address).await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let before = cpu_time();
+    tokio::time::sleep(WINDOW).await;
+    let used = cpu_time() - before;
+
+    assert!(
+        used < WINDOW / 10,
+        "{} idle connections used {:?} of CPU in {:?}",
+        CONNECTIONS,
+        used,
+        WINDOW
+    );
+}
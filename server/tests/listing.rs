@@ -0,0 +1,162 @@
+#![allow(clippy::needless_return)]
+
+use std::time::Duration;
+
+use server::{
+    game::Event,
+    listing::{self, Heartbeat},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::Instant,
+};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+
+// Reads one HTTP request off the connection, returning its body
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut data: Vec<u8> = vec![];
+    let mut chunk = [0; 1024];
+
+    let header_end = loop {
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&chunk[..read]);
+    };
+
+    let headers = String::from_utf8_lossy(&data[..header_end]).to_lowercase();
+    let length: usize = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .map(|length| length.trim().parse().unwrap())
+        .unwrap_or(0);
+
+    while data.len() < header_end + length {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&chunk[..read]);
+    }
+
+    return Some(String::from_utf8_lossy(&data[header_end..header_end + length]).to_string());
+}
+
+// Stands in for the listing, answering posts with `statuses` in turn then 200 from there on. A
+// `None` never answers at all. Reports when each post came in.
+async fn serve(
+    listener: TcpListener,
+    statuses: Vec<Option<&'static str>>,
+    posts: mpsc::UnboundedSender<(Instant, String)>,
+) {
+    let mut statuses = statuses.into_iter();
+    let mut stalled: Vec<TcpStream> = vec![];
+
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        while let Some(body) = read_request(&mut stream).await {
+            if posts.send((Instant::now(), body)).is_err() {
+                return;
+            }
+
+            let Some(status) = statuses.next().unwrap_or(Some("200 OK")) else {
+                // Hold on to the connection without answering
+                stalled.push(stream);
+                break;
+            };
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            if stream.write_all(response.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// Runs `listing::run` against a stand-in listing, returning when each post came in and its body
+async fn start(statuses: Vec<Option<&'static str>>) -> mpsc::UnboundedReceiver<(Instant, String)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/postServer", listener.local_addr().unwrap());
+
+    let (posts_sender, posts) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, statuses, posts_sender));
+
+    // Answers the heartbeat requests the game task would
+    let (events, mut receiver) = mpsc::channel(8);
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if let Event::Listing(reply) = event {
+                let _ = reply.send(Heartbeat {
+                    host_key: "key".to_string(),
+                    port: 42480,
+                    players: 3,
+                    map: "map.brk".to_string(),
+                    brick_count: 12,
+                });
+            }
+        }
+    });
+
+    tokio::spawn(listing::run(listing::new(url), events, INTERVAL));
+    return posts;
+}
+
+fn assert_gaps(times: &[Instant], expected: &[u64]) {
+    for (gap, seconds) in times.windows(2).zip(expected) {
+        let gap = gap[1] - gap[0];
+        assert!(
+            gap >= Duration::from_secs(*seconds) && gap < Duration::from_secs(seconds + 1),
+            "expected about {}s between posts, got {:?}",
+            seconds,
+            gap
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn posts_heartbeats_and_backs_off() {
+    let error = Some("500 Internal Server Error");
+    let mut posts = start(vec![error, error, error]).await;
+
+    let mut times: Vec<Instant> = vec![];
+    for _ in 0..5 {
+        let (time, body) = posts.recv().await.unwrap();
+        times.push(time);
+
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "host_key": "key",
+                "port": 42480,
+                "players": 3,
+                "map": "map.brk",
+                "brick_count": 12,
+            })
+        );
+    }
+
+    // Failures retry after 5, 10 then 20 seconds, and a success waits out the interval
+    assert_gaps(&times, &[5, 10, 20, 60]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_posts_time_out_and_retry() {
+    let mut posts = start(vec![None]).await;
+
+    let mut times: Vec<Instant> = vec![];
+    for _ in 0..3 {
+        times.push(posts.recv().await.unwrap().0);
+    }
+
+    // The stalled post gives up after the timeout, then backs off like any other failure
+    let timed_out = listing::POST_TIMEOUT.as_secs() + 5;
+    assert_gaps(&times, &[timed_out, 60]);
+}
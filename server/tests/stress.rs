@@ -0,0 +1,85 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use server::{
+    buffer::{self, Buffer},
+    game,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinSet,
+    time,
+};
+
+const CLIENTS: usize = 200;
+const POSITIONS: usize = 20;
+
+fn auth_packet() -> Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(1);
+    packet.write_string("token".to_string());
+    packet.write_string("0.3.1.0".to_string());
+    packet.write_uint_v();
+    return packet;
+}
+
+fn position_packet(step: usize) -> Buffer {
+    let mut packet = buffer::new(None);
+    packet.write_byte(2);
+    packet.write_float32(step as f32);
+    packet.write_float32(0.0);
+    packet.write_float32(10.0);
+    packet.write_float32(0.0);
+    packet.write_uint_v();
+    return packet;
+}
+
+// Authenticates and moves around a bit, returning the net id the server assigned
+async fn join(address: std::net::SocketAddr) -> u32 {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+
+    stream.write_all(&auth_packet().data).await.unwrap();
+    for step in 0..POSITIONS {
+        stream.write_all(&position_packet(step).data).await.unwrap();
+    }
+
+    let mut pending: Vec<u8> = vec![];
+    let mut data = [0; 4096];
+    loop {
+        while let Some(mut message) = buffer::next_message(&mut pending).unwrap() {
+            if message.read_byte() == 1 {
+                return message.read_uint32().unwrap();
+            }
+        }
+
+        let read = stream.read(&mut data).await.unwrap();
+        assert!(read > 0, "server closed the connection before auth");
+        pending.extend_from_slice(&data[..read]);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_clients_all_join() {
+    let address = common::start(game::new(), Duration::from_secs(60)).await;
+
+    let mut clients = JoinSet::new();
+    for _ in 0..CLIENTS {
+        clients.spawn(join(address));
+    }
+
+    let mut net_ids: HashSet<u32> = HashSet::new();
+    let joined = time::timeout(Duration::from_secs(30), async {
+        while let Some(net_id) = clients.join_next().await {
+            net_ids.insert(net_id.unwrap());
+        }
+    })
+    .await;
+
+    assert!(joined.is_ok(), "only {} clients joined", net_ids.len());
+    assert_eq!(net_ids.len(), CLIENTS);
+}